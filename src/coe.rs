@@ -0,0 +1,131 @@
+use crate::error::{AssemblerError, Result as AssemblerResult};
+
+pub const RADIX_KEY: &str = "memory_initialization_radix";
+pub const VECTOR_KEY: &str = "memory_initialization_vector";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoeRadix {
+    Binary,
+    Decimal { signed: bool },
+    Hex,
+}
+
+impl CoeRadix {
+    pub fn radix(&self) -> u32 {
+        match self {
+            CoeRadix::Binary => 2,
+            CoeRadix::Decimal { .. } => 10,
+            CoeRadix::Hex => 16,
+        }
+    }
+}
+
+/// Summary of a structurally valid COE file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CoeSummary {
+    pub radix: u32,
+    pub words: usize,
+}
+
+/// Checks the header, separators, terminator and word digits of a COE
+/// file. Fixed-width radixes must use the same digit count for every word.
+pub fn validate(text: &str) -> AssemblerResult<CoeSummary> {
+    let invalid = |msg: String| AssemblerError::InvalidCoe(msg);
+    // Whole-line `;` comments are allowed by the format
+    let body: Vec<_> = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .collect();
+    let body = body.join("\n");
+
+    let rest = body
+        .strip_prefix(RADIX_KEY)
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+        .ok_or_else(|| invalid(format!("missing `{}=`", RADIX_KEY)))?;
+    let (radix_str, rest) = rest
+        .split_once(';')
+        .ok_or_else(|| invalid(String::from("unterminated radix declaration")))?;
+    let radix = match radix_str.trim() {
+        "2" => 2,
+        "10" => 10,
+        "16" => 16,
+        other => return Err(invalid(format!("unsupported radix `{}`", other))),
+    };
+
+    let vector = rest
+        .trim_start()
+        .strip_prefix(VECTOR_KEY)
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+        .ok_or_else(|| invalid(format!("missing `{}=`", VECTOR_KEY)))?;
+    let vector = vector.trim_end();
+    let vector = vector
+        .strip_suffix(';')
+        .ok_or_else(|| invalid(String::from("vector is not terminated by `;`")))?;
+    if vector.contains(';') {
+        return Err(invalid(String::from("unexpected `;` inside vector")));
+    }
+
+    // An empty program is just the terminator
+    if vector.trim().is_empty() {
+        return Ok(CoeSummary { radix, words: 0 });
+    }
+    let mut digits = None;
+    let mut words = 0;
+    for (idx, word) in vector.split(',').map(|word| word.trim()).enumerate() {
+        if word.is_empty() {
+            return Err(invalid(format!("empty word at index {}", idx)));
+        }
+        let unsigned = if radix == 10 {
+            word.strip_prefix('-').unwrap_or(word)
+        } else {
+            word
+        };
+        if !unsigned.chars().all(|c| c.is_digit(radix)) {
+            return Err(invalid(format!(
+                "word `{}` at index {} is not valid in radix {}",
+                word, idx, radix
+            )));
+        }
+        if radix != 10 {
+            match digits {
+                None => digits = Some(word.len()),
+                Some(width) if width != word.len() => {
+                    return Err(invalid(format!(
+                        "word `{}` at index {} has {} digits, expected {}",
+                        word,
+                        idx,
+                        word.len(),
+                        width
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+        words += 1;
+    }
+    Ok(CoeSummary { radix, words })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_coe() {
+        let valid = "memory_initialization_radix=2;\nmemory_initialization_vector=\n0101,\n1100;\n";
+        assert_eq!(validate(valid).unwrap(), CoeSummary { radix: 2, words: 2 });
+        let unterminated =
+            "memory_initialization_radix=2;\nmemory_initialization_vector=\n0101,\n1100\n";
+        assert!(matches!(
+            validate(unterminated),
+            Err(AssemblerError::InvalidCoe(msg)) if msg.contains("not terminated")
+        ));
+        let ragged = "memory_initialization_radix=16;\nmemory_initialization_vector=\n0a,\n1c0;";
+        assert!(validate(ragged).is_err());
+        let bad_digit = "memory_initialization_radix=2;\nmemory_initialization_vector=\n0102;";
+        assert!(validate(bad_digit).is_err());
+        let empty = "memory_initialization_radix=2;\nmemory_initialization_vector=;\n";
+        assert_eq!(validate(empty).unwrap(), CoeSummary { radix: 2, words: 0 });
+    }
+}
@@ -13,12 +13,64 @@ pub enum AssemblerError {
     UnknownRegister(String),
     #[error("failed to parse number `{0}`")]
     InvalidNumber(String),
+    #[error("invalid expression `{0}`")]
+    InvalidExpression(String),
+    #[error("immediate `{value}` does not fit in {bits} bits")]
+    ImmediateOutOfRange { value: String, bits: u32 },
+    #[error("shift amount `{0}` is out of range 0-31")]
+    ShiftAmountOutOfRange(u8),
+    #[error("pseudo-instruction `{0}` is not allowed")]
+    PseudoInstruction(String),
     #[error("invalid instruction format `{0}`")]
     InvalidInstruction(String),
+    #[error("non-canonical format `{found}`, expected `{expected}`")]
+    NonCanonicalFormat { found: String, expected: String },
+    #[error("undefined label `{0}`")]
+    UndefinedLabel(String),
+    #[error("label `{0}` is defined more than once")]
+    DuplicateLabel(String),
+    #[error("constant `{0}` is redefined with a different value")]
+    DuplicateConstant(String),
+    #[error("label `{label}` shadows a {kind} name")]
+    ShadowingLabel { label: String, kind: &'static str },
+    #[error("no entry label declared with `.entry` or `.global`")]
+    MissingEntry,
+    #[error("`.org {addr:#x}` would move code placed up to `{current:#x}` backward")]
+    OrgBackwards { addr: u32, current: u32 },
+    #[error("`.org {0:#x}` is not word-aligned")]
+    UnalignedOrg(u32),
+    #[error("label offset `{0}` is not a multiple of 4")]
+    UnalignedOffset(String),
+    #[error("address `{0:#x}` is outside the 32-bit address space")]
+    AddressOutOfRange(u64),
+    #[error("cannot allocate `{0}` words of padding")]
+    PaddingTooLarge(usize),
+    #[error("branch to `{label}` is {offset} words away, beyond a 16-bit offset")]
+    BranchOutOfRange { label: String, offset: i64 },
     #[error("no address attached to label `{0}`")]
     FloatingLabel(String),
+    #[error("encoding `{value:#x}` does not fit in a {width}-bit word")]
+    WordTooWide { value: u64, width: u32 },
+    #[error("program needs `{needed}` words but memory depth is `{depth}`")]
+    ImageTooLarge { needed: usize, depth: usize },
+    #[error("image needs `{needed}` bytes but is limited to `{size}`")]
+    ImageSizeExceeded { needed: usize, size: usize },
+    #[error("image CRC `{actual:#010x}` does not match expected `{expected:#010x}`")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[error("image size `{0}` is not a whole number of words")]
+    InvalidImageSize(usize),
+    #[error("gzip input requires building with the `flate2` feature")]
+    GzipUnsupported,
+    #[error("invalid COE: {0}")]
+    InvalidCoe(String),
+    #[error("invalid MIF: {0}")]
+    InvalidMif(String),
+    #[error("invalid ISA table: {0}")]
+    InvalidIsaTable(String),
+    #[error("could not send image to `{target}`: {source}")]
+    EmitFailed { target: String, source: io::Error },
     #[error("io error: {0}")]
     IOError(#[from] io::Error),
 }
 
-pub type Result<T> = StdResult<T, AssemblerError>;
+pub type Result<T, E = AssemblerError> = StdResult<T, E>;
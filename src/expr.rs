@@ -0,0 +1,196 @@
+use crate::{
+    error::{AssemblerError, Result as AssemblerResult},
+    instruction::{self, Constants},
+};
+use std::num::IntErrorKind;
+
+/// Largest shift an expression may apply; immediates are at most 32 bits.
+const MAX_SHIFT: i64 = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    // A number or constant name
+    Atom(&'a str),
+    Op(&'a str),
+}
+
+fn tokenize(text: &str) -> AssemblerResult<Vec<Token<'_>>> {
+    let invalid = || AssemblerError::InvalidExpression(String::from(text));
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let atom_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let len = if atom_len > 0 {
+            tokens.push(Token::Atom(&rest[..atom_len]));
+            atom_len
+        } else {
+            let len = if rest.starts_with("<<") || rest.starts_with(">>") {
+                2
+            } else if rest.starts_with(['+', '-', '*', '(', ')']) {
+                1
+            } else {
+                return Err(invalid());
+            };
+            tokens.push(Token::Op(&rest[..len]));
+            len
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent evaluator, loosest binding first:
+/// shifts, then `+`/`-`, then `*`, then a single unary sign.
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    constants: &'a Constants,
+}
+
+impl<'a> Parser<'a> {
+    fn invalid(&self) -> AssemblerError {
+        AssemblerError::InvalidExpression(String::from(self.text))
+    }
+
+    fn peek_op(&self) -> Option<&'a str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn shift(&mut self) -> AssemblerResult<i64> {
+        let mut value = self.additive()?;
+        while let Some(op @ ("<<" | ">>")) = self.peek_op() {
+            self.pos += 1;
+            let amount = self.additive()?;
+            if !(0..=MAX_SHIFT).contains(&amount) {
+                return Err(self.invalid());
+            }
+            value = match op {
+                "<<" => value.saturating_mul(1 << amount),
+                _ => value >> amount,
+            };
+        }
+        Ok(value)
+    }
+
+    fn additive(&mut self) -> AssemblerResult<i64> {
+        let mut value = self.term()?;
+        while let Some(op @ ("+" | "-")) = self.peek_op() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = match op {
+                "+" => value.saturating_add(rhs),
+                _ => value.saturating_sub(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> AssemblerResult<i64> {
+        let mut value = self.unary()?;
+        while self.peek_op() == Some("*") {
+            self.pos += 1;
+            value = value.saturating_mul(self.unary()?);
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> AssemblerResult<i64> {
+        match self.peek_op() {
+            Some("-") => {
+                self.pos += 1;
+                Ok(self.primary()?.saturating_neg())
+            }
+            Some("+") => {
+                self.pos += 1;
+                self.primary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> AssemblerResult<i64> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        match token {
+            Some(Token::Atom(atom)) => self.atom(atom),
+            Some(Token::Op("(")) => {
+                let value = self.shift()?;
+                if self.peek_op() != Some(")") {
+                    return Err(self.invalid());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn atom(&self, atom: &str) -> AssemblerResult<i64> {
+        if let Some(&value) = self.constants.get(atom) {
+            return Ok(value);
+        }
+        let (radix, digits) = instruction::parse_radix(atom);
+        match u64::from_str_radix(digits, radix) {
+            Ok(value) => Ok(i64::try_from(value).unwrap_or(i64::MAX)),
+            // Too large for any field, so saturating still fails the range check
+            Err(err) if *err.kind() == IntErrorKind::PosOverflow => Ok(i64::MAX),
+            Err(_) => Err(AssemblerError::InvalidNumber(String::from(digits))),
+        }
+    }
+}
+
+/// Evaluates an operand such as `BASE + 4` or `(1 << 4) - 1` over
+/// numbers in any supported radix and `.equ` constants.
+pub fn evaluate(text: &str, constants: &Constants) -> AssemblerResult<i64> {
+    let mut parser = Parser {
+        text,
+        tokens: tokenize(text)?,
+        pos: 0,
+        constants,
+    };
+    let value = parser.shift()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.invalid());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let constants = Constants::from([(String::from("BASE"), 0x100)]);
+        let eval = |text| evaluate(text, &constants);
+        assert_eq!(eval("2 + 3 * 4").unwrap(), 14);
+        assert_eq!(eval("(1 << 4) - 1").unwrap(), 15);
+        assert_eq!(eval("BASE + 4").unwrap(), 0x104);
+        assert_eq!(eval("-0x10 >> 2").unwrap(), -4);
+        assert_eq!(eval("0b101").unwrap(), 5);
+        // Negating the saturated minimum must not overflow
+        assert_eq!(eval("-(0 - 0xFFFFFFFFFFFFFFFF - 1)").unwrap(), i64::MAX);
+        assert!(matches!(
+            eval("2 +"),
+            Err(AssemblerError::InvalidExpression(text)) if text == "2 +"
+        ));
+        assert!(matches!(
+            eval("(1 + 2"),
+            Err(AssemblerError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            eval("1 << 40"),
+            Err(AssemblerError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            eval("LIMIT"),
+            Err(AssemblerError::InvalidNumber(_))
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+/// Bytes per word in a raw binary image.
+pub const WORD_BYTES: usize = 4;
+
+/// A word that differs between two images. A side is `None` when that
+/// image ends before `addr`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WordDiff {
+    pub addr: usize,
+    pub old: Option<u32>,
+    pub new: Option<u32>,
+}
+
+fn words(image: &[u8]) -> Vec<u32> {
+    image
+        .chunks(WORD_BYTES)
+        .map(|chunk| {
+            // A trailing partial word is zero-padded on the right
+            let mut bytes = [0; WORD_BYTES];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(bytes)
+        })
+        .collect()
+}
+
+/// Compares two big-endian binary images word by word.
+pub fn diff(first: &[u8], second: &[u8]) -> Vec<WordDiff> {
+    let (old, new) = (words(first), words(second));
+    (0..old.len().max(new.len()))
+        .map(|idx| WordDiff {
+            addr: WORD_BYTES * idx,
+            old: old.get(idx).copied(),
+            new: new.get(idx).copied(),
+        })
+        .filter(|diff| diff.old != diff.new)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_images() {
+        let first = [0x09, 0x00, 0x00, 0x01, 0x01, 0x09, 0x00, 0x00];
+        let mut second = first;
+        second[6] = 0x80;
+        assert!(diff(&first, &first).is_empty());
+        assert_eq!(
+            diff(&first, &second),
+            vec![WordDiff {
+                addr: 4,
+                old: Some(0x0109_0000),
+                new: Some(0x0109_8000)
+            }]
+        );
+        assert_eq!(
+            diff(&first, &first[..4]),
+            vec![WordDiff {
+                addr: 4,
+                old: Some(0x0109_0000),
+                new: None
+            }]
+        );
+    }
+}
@@ -1,8 +1,11 @@
 use colored::*;
-use num_traits::{AsPrimitive, Num};
 use regex::Regex;
+use std::{collections::HashMap, fmt};
 
-use crate::error::{AssemblerError, Result as AssemblerResult};
+use crate::{
+    error::{AssemblerError, Result as AssemblerResult},
+    expr, isa,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Instruction {
@@ -28,37 +31,23 @@ pub enum Instruction {
     Bltz { rs: u8, label: RelLabel },
     Bz { rs: u8, label: RelLabel },
     Bnz { rs: u8, label: RelLabel },
+    // A literal word from `.word`, laid out like an instruction
+    Data { value: u32 },
+    // A label's address from `.word`, such as a jump table entry
+    Address { label: AbsLabel },
 }
 
 impl Instruction {
+    /// `add $zero, $zero`, which encodes to the all-zero word.
+    pub const NOP: Instruction = Instruction::Add { rs: 0, rt: 0 };
+
     fn opcode(&self) -> u8 {
-        match self {
-            Self::Add { .. } => 0,
-            Self::Comp { .. } => 1,
-            Self::AddImm { .. } => 2,
-            Self::CompImm { .. } => 3,
-            Self::And { .. } => 4,
-            Self::Xor { .. } => 5,
-            Self::Sll { .. } => 6,
-            Self::Srl { .. } => 7,
-            Self::Sra { .. } => 10,
-            Self::Sllv { .. } => 8,
-            Self::Srlv { .. } => 9,
-            Self::Srav { .. } => 11,
-            Self::Lw { .. } => 12,
-            Self::Sw { .. } => 13,
-            Self::B { .. } => 14,
-            Self::Bl { .. } => 19,
-            Self::Br { .. } => 15,
-            Self::Bcy { .. } => 20,
-            Self::Bncy { .. } => 21,
-            Self::Bltz { .. } => 16,
-            Self::Bz { .. } => 17,
-            Self::Bnz { .. } => 18,
-        }
-    }
-
-    fn opname(&self) -> &'static str {
+        isa::lookup(self.opname())
+            .map(|info| info.opcode)
+            .unwrap_or_else(|| unreachable!("{} missing from ISA table", self.opname()))
+    }
+
+    pub fn opname(&self) -> &'static str {
         match self {
             Self::Add { .. } => "add",
             Self::Comp { .. } => "comp",
@@ -82,11 +71,20 @@ impl Instruction {
             Self::Bltz { .. } => "bltz",
             Self::Bz { .. } => "bz",
             Self::Bnz { .. } => "bnz",
+            Self::Data { .. } | Self::Address { .. } => ".word",
         }
     }
 
     pub fn encode(&self) -> AssemblerResult<u32> {
-        let opcode = self.opcode();
+        match self {
+            Instruction::Data { .. } | Instruction::Address { .. } => self.encode_with(0),
+            _ => self.encode_with(self.opcode()),
+        }
+    }
+
+    /// Encodes the operands under an alternative opcode, e.g. one taken
+    /// from an edited ISA table.
+    pub fn encode_with(&self, opcode: u8) -> AssemblerResult<u32> {
         match self {
             Instruction::Add { rs, rt }
             | Instruction::Comp { rs, rt }
@@ -108,11 +106,17 @@ impl Instruction {
                 Ok(encode_itype(opcode, *rs, *rt, *imm))
             }
 
-            Instruction::B { label } | Instruction::Bl { label } => match label.addr {
+            Instruction::B { label } | Instruction::Bl { label } => match label.addr() {
+                Some(addr) if addr >> JTYPE_ADDR_BITS != 0 => {
+                    Err(AssemblerError::ImmediateOutOfRange {
+                        value: label.to_string(),
+                        bits: JTYPE_ADDR_BITS,
+                    })
+                }
                 Some(addr) => Ok(encode_jtype(opcode, addr)),
                 None => Err(AssemblerError::FloatingLabel(label.name.clone())),
             },
-            Instruction::Bcy { label } | Instruction::Bncy { label } => match label.addr {
+            Instruction::Bcy { label } | Instruction::Bncy { label } => match label.addr() {
                 Some(addr) => Ok(encode_itype(opcode, 0, 0, addr)),
                 None => Err(AssemblerError::FloatingLabel(label.name.clone())),
             },
@@ -120,18 +124,153 @@ impl Instruction {
             Instruction::Br { rs } => Ok(encode_itype(opcode, *rs, 0, 0)),
             Instruction::Bltz { rs, label }
             | Instruction::Bz { rs, label }
-            | Instruction::Bnz { rs, label } => match label.addr {
+            | Instruction::Bnz { rs, label } => match label.addr() {
                 Some(addr) => Ok(encode_itype(opcode, *rs, 0, addr)),
                 None => Err(AssemblerError::FloatingLabel(label.name.clone())),
             },
+
+            Instruction::Data { value } => Ok(*value),
+            Instruction::Address { label } => label
+                .addr()
+                .ok_or_else(|| AssemblerError::FloatingLabel(label.name.clone())),
+        }
+    }
+
+    pub fn encode_word(&self, width: u32) -> AssemblerResult<Word> {
+        let opcode = if self.is_data() { 0 } else { self.opcode() };
+        self.encode_word_with(opcode, width)
+    }
+
+    /// Encodes into a `width`-bit word. Below 32 bits the opcode and
+    /// register fields keep their sizes at the top of the word and the
+    /// immediate or jump-address field shrinks to the bits left over, so
+    /// a 24-bit word has 8-bit immediates and a 16-bit one has none.
+    /// Wider words hold the 32-bit encoding unchanged.
+    pub fn encode_word_with(&self, opcode: u8, width: u32) -> AssemblerResult<Word> {
+        let value = self.encode_with(opcode)?;
+        if self.is_data() || width >= DEFAULT_WORD_WIDTH {
+            return Word::checked(value as u64, width);
+        }
+        if width < MIN_INSTR_WIDTH {
+            return Err(AssemblerError::WordTooWide {
+                value: value as u64,
+                width,
+            });
+        }
+        let opcode_field = (value >> 26) << (width - 6);
+        let narrow = match self {
+            Instruction::B { label } | Instruction::Bl { label } => {
+                let bits = width - 6;
+                let addr = value & ((1 << JTYPE_ADDR_BITS) - 1);
+                if addr >> bits != 0 {
+                    return Err(AssemblerError::ImmediateOutOfRange {
+                        value: label.to_string(),
+                        bits,
+                    });
+                }
+                opcode_field | addr
+            }
+            _ => {
+                let bits = width - MIN_INSTR_WIDTH;
+                let imm = value as u16;
+                // Either reading of the narrower field is accepted
+                let signed = imm as i16 as i32;
+                let fits = (imm as u32) >> bits == 0
+                    || (bits > 0 && signed < 0 && signed >= -(1 << (bits - 1)));
+                if !fits {
+                    return Err(AssemblerError::ImmediateOutOfRange {
+                        value: signed.to_string(),
+                        bits,
+                    });
+                }
+                let regs = (value >> 16) & 0x3FF;
+                opcode_field | (regs << bits) | (imm as u32 & ((1 << bits) - 1))
+            }
+        };
+        Word::checked(narrow as u64, width)
+    }
+
+    /// Registers whose values the instruction consumes. Operations
+    /// are two-operand, so `rs` is both a source and the destination.
+    pub fn reads(&self) -> Vec<u8> {
+        use Instruction::*;
+        match self {
+            Add { rs, rt }
+            | And { rs, rt }
+            | Xor { rs, rt }
+            | Sllv { rs, rt }
+            | Srlv { rs, rt }
+            | Srav { rs, rt } => vec![*rs, *rt],
+            Comp { rt, .. } => vec![*rt],
+            AddImm { rs, .. } | Sll { rs, .. } | Srl { rs, .. } | Sra { rs, .. } => vec![*rs],
+            Lw { rs, .. } => vec![*rs],
+            Sw { rt, rs, .. } => vec![*rt, *rs],
+            Br { rs } | Bltz { rs, .. } | Bz { rs, .. } | Bnz { rs, .. } => vec![*rs],
+            CompImm { .. }
+            | B { .. }
+            | Bl { .. }
+            | Bcy { .. }
+            | Bncy { .. }
+            | Data { .. }
+            | Address { .. } => vec![],
         }
     }
 
+    /// The register the instruction writes, if any. Writes to `$zero`
+    /// are discarded by the hardware, so they are not reported.
+    pub fn writes(&self) -> Option<u8> {
+        use Instruction::*;
+        let reg = match self {
+            Add { rs, .. }
+            | Comp { rs, .. }
+            | AddImm { rs, .. }
+            | CompImm { rs, .. }
+            | And { rs, .. }
+            | Xor { rs, .. }
+            | Sll { rs, .. }
+            | Srl { rs, .. }
+            | Sra { rs, .. }
+            | Sllv { rs, .. }
+            | Srlv { rs, .. }
+            | Srav { rs, .. } => *rs,
+            Lw { rt, .. } => *rt,
+            Bl { .. } => register_from_str("$ra").unwrap(),
+            Sw { .. }
+            | B { .. }
+            | Br { .. }
+            | Bcy { .. }
+            | Bncy { .. }
+            | Bltz { .. }
+            | Bz { .. }
+            | Bnz { .. }
+            | Data { .. }
+            | Address { .. } => return None,
+        };
+        (reg != 0).then_some(reg)
+    }
+
+    /// Whether execution can continue with the next instruction;
+    /// calls are assumed to return.
+    pub fn falls_through(&self) -> bool {
+        use Instruction::*;
+        !matches!(self, B { .. } | Br { .. })
+    }
+
+    pub fn is_data(&self) -> bool {
+        matches!(self, Instruction::Data { .. } | Instruction::Address { .. })
+    }
+
     pub fn has_abs_label(&self) -> bool {
         use Instruction::*;
         matches!(self, B { .. } | Bl { .. })
     }
 
+    /// Whether the instruction names a label, as a branch target or as a
+    /// `.word` operand.
+    pub fn has_label(&self) -> bool {
+        self.abs_label().is_some() || self.has_rel_label()
+    }
+
     pub fn has_rel_label(&self) -> bool {
         use Instruction::*;
         matches!(
@@ -143,7 +282,7 @@ impl Instruction {
     pub fn set_abs_addr(&mut self, addr: u32) {
         use Instruction::*;
         match self {
-            B { label, .. } | Bl { label, .. } => label.addr = Some(addr),
+            B { label, .. } | Bl { label, .. } | Address { label } => label.addr = Some(addr),
             _ => {
                 eprintln!(
                     "{} {}",
@@ -172,25 +311,48 @@ impl Instruction {
         }
     }
 
-    pub fn get_label_name(&self) -> &String {
+    pub fn abs_label(&self) -> Option<&AbsLabel> {
+        match self {
+            Instruction::B { label }
+            | Instruction::Bl { label }
+            | Instruction::Address { label } => Some(label),
+            _ => None,
+        }
+    }
+
+    pub fn rel_label(&self) -> Option<&RelLabel> {
         use Instruction::*;
         match self {
             Bcy { label, .. }
             | Bncy { label, .. }
             | Bltz { label, .. }
             | Bz { label, .. }
-            | Bnz { label, .. } => &label.name,
-
-            B { label } | Bl { label } => &label.name,
-            _ => unreachable!("didn't expect to get label of {}", self.opname()),
+            | Bnz { label, .. } => Some(label),
+            _ => None,
         }
     }
 
+    pub fn get_label_name(&self) -> &str {
+        self.abs_label()
+            .map(AbsLabel::name)
+            .or_else(|| self.rel_label().map(RelLabel::name))
+            .unwrap_or_else(|| unreachable!("didn't expect to get label of {}", self.opname()))
+    }
+
+    // Yields the expansion of a whole line rather than a single `Self`,
+    // so it can't be `FromStr`
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(instr: &str) -> AssemblerResult<Vec<Self>> {
+        Self::from_str_with(instr, &Constants::new())
+    }
+
+    /// Like `from_str`, resolving named immediates from `constants`.
+    pub fn from_str_with(instr: &str, constants: &Constants) -> AssemblerResult<Vec<Self>> {
         let mut instrs = Vec::new();
         let (comm, rest) = extract_command(instr)
             .ok_or_else(|| AssemblerError::OpcodeMissing(String::from(instr)))?;
-        match comm {
+        // Mnemonics are case-insensitive; operands keep their case
+        match comm.to_ascii_lowercase().as_str() {
             "push" => {
                 let reg = register_from_str(rest)
                     .ok_or_else(|| AssemblerError::UnknownRegister(String::from(rest)))?;
@@ -216,17 +378,84 @@ impl Instruction {
                     imm: 0,
                 });
             }
-            "mov" => {
+            "mov" | "move" => {
                 let (dest, src) = parse_two_registers(rest)?;
                 instrs.push(Instruction::Xor { rs: dest, rt: dest });
                 instrs.push(Instruction::Add { rs: dest, rt: src });
             }
-            _ => instrs.push(Instruction::try_from(instr)?),
+            ".word" => {
+                let values = split_operands(rest);
+                if values.is_empty() {
+                    return Err(AssemblerError::InvalidNoOfArgs(1, 0));
+                }
+                for value in values {
+                    instrs.push(parse_data_word(value, constants)?);
+                }
+            }
+            "li" => {
+                let (dest, imm) = parse_register_and_immediate(rest, constants)?;
+                instrs.push(Instruction::Xor { rs: dest, rt: dest });
+                instrs.push(Instruction::AddImm { rs: dest, imm });
+            }
+            // `li64 $hi, $lo, value` puts the upper 32 bits of the value in
+            // the first register and the lower 32 bits in the second
+            "li64" => {
+                let operands = split_operands(rest);
+                if operands.len() != 3 {
+                    return Err(AssemblerError::InvalidNoOfArgs(3, operands.len()));
+                }
+                let (hi, lo) = parse_two_registers(&rest[..rest.rfind(',').unwrap()])?;
+                if hi == lo {
+                    return Err(AssemblerError::InvalidInstruction(String::from(instr)));
+                }
+                let value = parse_value(operands[2], constants)? as u64;
+                push_load_word(&mut instrs, hi, (value >> 32) as u32);
+                push_load_word(&mut instrs, lo, value as u32);
+            }
+            _ => instrs.push(parse_instruction(instr, constants)?),
         }
         Ok(instrs)
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        let op = self.opname();
+        match self {
+            Add { rs, rt }
+            | Comp { rs, rt }
+            | And { rs, rt }
+            | Xor { rs, rt }
+            | Sllv { rs, rt }
+            | Srlv { rs, rt }
+            | Srav { rs, rt } => write!(f, "{} {}, {}", op, register_name(*rs), register_name(*rt)),
+            AddImm { rs, imm } | CompImm { rs, imm } => {
+                write!(f, "{} {}, {}", op, register_name(*rs), *imm as i16)
+            }
+            Sll { rs, sh } | Srl { rs, sh } | Sra { rs, sh } => {
+                write!(f, "{} {}, {}", op, register_name(*rs), sh)
+            }
+            Lw { rt, imm, rs } | Sw { rt, imm, rs } => write!(
+                f,
+                "{} {}, {}({})",
+                op,
+                register_name(*rt),
+                *imm as i16,
+                register_name(*rs)
+            ),
+            B { label } | Bl { label } => write!(f, "{} {}", op, label),
+            Br { rs } => write!(f, "{} {}", op, register_name(*rs)),
+            Bcy { label } | Bncy { label } => write!(f, "{} {}", op, label.name()),
+            Bltz { rs, label } | Bz { rs, label } | Bnz { rs, label } => {
+                write!(f, "{} {}, {}", op, register_name(*rs), label.name())
+            }
+            Data { value } => write!(f, "{} {:#010x}", op, value),
+            Address { label } => write!(f, "{} {}", op, label),
+        }
+    }
+}
+
 fn encode_itype(opcode: u8, rs: u8, rt: u8, imm: u16) -> u32 {
     let mut instr = 0_u32;
     instr |= (opcode as u32) << 26;
@@ -236,6 +465,9 @@ fn encode_itype(opcode: u8, rs: u8, rt: u8, imm: u16) -> u32 {
     instr
 }
 
+/// Width of the byte-address field in `b` and `bl`.
+pub const JTYPE_ADDR_BITS: u32 = 26;
+
 fn encode_jtype(opcode: u8, addr: u32) -> u32 {
     let mut instr = 0_u32;
     instr |= (opcode as u32) << 26;
@@ -243,104 +475,184 @@ fn encode_jtype(opcode: u8, addr: u32) -> u32 {
     instr
 }
 
+pub const DEFAULT_WORD_WIDTH: u32 = 32;
+
+/// Narrowest word that still holds an opcode and two register fields.
+pub const MIN_INSTR_WIDTH: u32 = 16;
+
+/// An encoded instruction-memory word, masked to a declared bit width
+/// so that variant ISAs with narrower words can share the encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Word {
+    value: u64,
+    width: u32,
+}
+
+impl Word {
+    pub fn new(value: u64, width: u32) -> Self {
+        let mask = if width >= 64 {
+            u64::MAX
+        } else {
+            (1_u64 << width) - 1
+        };
+        Self {
+            value: value & mask,
+            width,
+        }
+    }
+
+    /// Like `new`, but fails instead of dropping high bits, so an
+    /// encoding never silently turns into a different instruction.
+    pub fn checked(value: u64, width: u32) -> AssemblerResult<Self> {
+        let word = Self::new(value, width);
+        if word.value != value {
+            return Err(AssemblerError::WordTooWide { value, width });
+        }
+        Ok(word)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The word read as a two's-complement number of its own width.
+    pub fn signed_value(self) -> i64 {
+        if self.width == 0 || self.width >= 64 {
+            return self.value as i64;
+        }
+        let shift = 64 - self.width;
+        ((self.value << shift) as i64) >> shift
+    }
+
+    /// Zero-padded hex digits covering the whole word, e.g. `00000005`.
+    pub fn to_hex(self) -> String {
+        let digits = 2 * (self.width as usize).div_ceil(8);
+        format!("{:0digits$x}", self.value, digits = digits)
+    }
+
+    /// Big-endian bytes covering the whole word.
+    pub fn to_be_bytes(self) -> Vec<u8> {
+        let len = (self.width as usize).div_ceil(8);
+        self.value.to_be_bytes()[8 - len..].to_vec()
+    }
+
+    /// Little-endian bytes covering the whole word.
+    pub fn to_le_bytes(self) -> Vec<u8> {
+        let len = (self.width as usize).div_ceil(8);
+        self.value.to_le_bytes()[..len].to_vec()
+    }
+}
+
 impl TryFrom<&str> for Instruction {
     type Error = AssemblerError;
 
     fn try_from(instr: &str) -> Result<Self, Self::Error> {
-        let (comm, rest) = extract_command(instr)
-            .ok_or_else(|| AssemblerError::OpcodeMissing(String::from(instr)))?;
-        match comm {
-            "add" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Add { rs, rt })
-            }
-            "comp" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Comp { rs, rt })
-            }
-            "addi" => {
-                let (rs, imm) = parse_register_and_value::<u16>(rest)?;
-                Ok(Instruction::AddImm { rs, imm })
-            }
-            "compi" => {
-                let (rs, imm) = parse_register_and_value::<u16>(rest)?;
-                Ok(Instruction::CompImm { rs, imm })
-            }
-            "and" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::And { rs, rt })
-            }
-            "xor" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Xor { rs, rt })
-            }
-            "sll" => {
-                let (rs, sh) = parse_register_and_value::<u8>(rest)?;
-                Ok(Instruction::Sll { rs, sh })
-            }
-            "srl" => {
-                let (rs, sh) = parse_register_and_value::<u8>(rest)?;
-                Ok(Instruction::Srl { rs, sh })
-            }
-            "sra" => {
-                let (rs, sh) = parse_register_and_value::<u8>(rest)?;
-                Ok(Instruction::Sra { rs, sh })
-            }
-            "sllv" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Sllv { rs, rt })
-            }
-            "srlv" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Srlv { rs, rt })
-            }
-            "srav" => {
-                let (rs, rt) = parse_two_registers(rest)?;
-                Ok(Instruction::Srav { rs, rt })
-            }
-            "lw" => {
-                let (rt, imm, rs) = parse_mem_access(rest)?;
-                Ok(Instruction::Lw { rt, imm, rs })
-            }
-            "sw" => {
-                let (rt, imm, rs) = parse_mem_access(rest)?;
-                Ok(Instruction::Sw { rt, imm, rs })
-            }
-            "b" => Ok(Instruction::B {
-                label: AbsLabel::from(rest.trim()),
-            }),
-            "bl" => Ok(Instruction::Bl {
-                label: AbsLabel::from(rest.trim()),
-            }),
-            "br" => Ok(Instruction::Br {
-                rs: register_from_str(rest)
-                    .ok_or_else(|| AssemblerError::UnknownRegister(String::from(rest)))?,
-            }),
-            "bcy" => Ok(Instruction::Bcy {
-                label: RelLabel::from(rest.trim()),
-            }),
-            "bncy" => Ok(Instruction::Bncy {
-                label: RelLabel::from(rest.trim()),
-            }),
-            "bltz" => {
-                let (rs, label) = parse_reg_label(rest)?;
-                Ok(Instruction::Bltz { rs, label })
-            }
-            "bz" => {
-                let (rs, label) = parse_reg_label(rest)?;
-                Ok(Instruction::Bz { rs, label })
-            }
-            "bnz" => {
-                let (rs, label) = parse_reg_label(rest)?;
-                Ok(Instruction::Bnz { rs, label })
-            }
-            _ => Err(AssemblerError::UnknownInstruction(String::from(comm))),
+        parse_instruction(instr, &Constants::new())
+    }
+}
+
+fn parse_instruction(instr: &str, constants: &Constants) -> AssemblerResult<Instruction> {
+    let (comm, rest) =
+        extract_command(instr).ok_or_else(|| AssemblerError::OpcodeMissing(String::from(instr)))?;
+    // Mnemonics are case-insensitive; operands keep their case
+    match comm.to_ascii_lowercase().as_str() {
+        "add" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Add { rs, rt })
+        }
+        "comp" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Comp { rs, rt })
+        }
+        "addi" => {
+            let (rs, imm) = parse_register_and_immediate(rest, constants)?;
+            Ok(Instruction::AddImm { rs, imm })
+        }
+        "compi" => {
+            let (rs, imm) = parse_register_and_immediate(rest, constants)?;
+            Ok(Instruction::CompImm { rs, imm })
+        }
+        "and" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::And { rs, rt })
+        }
+        "xor" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Xor { rs, rt })
         }
+        "sll" => {
+            let (rs, sh) = parse_register_and_shamt(rest, constants)?;
+            Ok(Instruction::Sll { rs, sh })
+        }
+        "srl" => {
+            let (rs, sh) = parse_register_and_shamt(rest, constants)?;
+            Ok(Instruction::Srl { rs, sh })
+        }
+        "sra" => {
+            let (rs, sh) = parse_register_and_shamt(rest, constants)?;
+            Ok(Instruction::Sra { rs, sh })
+        }
+        "sllv" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Sllv { rs, rt })
+        }
+        "srlv" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Srlv { rs, rt })
+        }
+        "srav" => {
+            let (rs, rt) = parse_two_registers(rest)?;
+            Ok(Instruction::Srav { rs, rt })
+        }
+        "lw" => {
+            let (rt, imm, rs) = parse_mem_access(rest, constants)?;
+            Ok(Instruction::Lw { rt, imm, rs })
+        }
+        "sw" => {
+            let (rt, imm, rs) = parse_mem_access(rest, constants)?;
+            Ok(Instruction::Sw { rt, imm, rs })
+        }
+        "b" => Ok(Instruction::B {
+            label: parse_abs_label(rest, constants)?,
+        }),
+        "bl" => Ok(Instruction::Bl {
+            label: parse_abs_label(rest, constants)?,
+        }),
+        "br" => Ok(Instruction::Br {
+            rs: parse_single_register(rest)?,
+        }),
+        "bcy" => Ok(Instruction::Bcy {
+            label: RelLabel::from(parse_single_label(rest)?),
+        }),
+        "bncy" => Ok(Instruction::Bncy {
+            label: RelLabel::from(parse_single_label(rest)?),
+        }),
+        "bltz" => {
+            let (rs, label) = parse_register_and_label(rest)?;
+            Ok(Instruction::Bltz { rs, label })
+        }
+        "bz" => {
+            let (rs, label) = parse_register_and_label(rest)?;
+            Ok(Instruction::Bz { rs, label })
+        }
+        "bnz" => {
+            let (rs, label) = parse_register_and_label(rest)?;
+            Ok(Instruction::Bnz { rs, label })
+        }
+        "nop" => match split_operands(rest).len() {
+            0 => Ok(Instruction::NOP),
+            found => Err(AssemblerError::InvalidNoOfArgs(0, found)),
+        },
+        _ => Err(AssemblerError::UnknownInstruction(String::from(comm))),
     }
 }
 
 fn parse_two_registers(rest: &str) -> AssemblerResult<(u8, u8)> {
-    let regs_str: Vec<_> = rest.split(',').map(|x| x.trim()).collect();
+    let regs_str = split_operands(rest);
     if regs_str.len() != 2 {
         return Err(AssemblerError::InvalidNoOfArgs(2, regs_str.len()));
     }
@@ -354,25 +666,85 @@ fn parse_two_registers(rest: &str) -> AssemblerResult<(u8, u8)> {
     Ok((regs[0], regs[1]))
 }
 
-fn parse_register_and_value<T>(rest: &str) -> AssemblerResult<(u8, T)>
-where
-    T: Num + AsPrimitive<i32>,
-    i32: AsPrimitive<T>,
-{
-    let things_str: Vec<_> = rest.split(',').map(|x| x.trim()).collect();
+/// Loads a full 32-bit word with `li` followed by a shift and an `addi`
+/// of the low half. `addi` sign-extends, so the upper half is rounded
+/// up whenever bit 15 is set.
+fn push_load_word(instrs: &mut Vec<Instruction>, reg: u8, word: u32) {
+    let upper = (word.wrapping_add(0x8000) >> 16) as u16;
+    instrs.push(Instruction::Xor { rs: reg, rt: reg });
+    instrs.push(Instruction::AddImm {
+        rs: reg,
+        imm: upper,
+    });
+    instrs.push(Instruction::Sll { rs: reg, sh: 16 });
+    instrs.push(Instruction::AddImm {
+        rs: reg,
+        imm: word as u16,
+    });
+}
+
+fn parse_register_and_operand(rest: &str) -> AssemblerResult<(u8, &str)> {
+    let things_str = split_operands(rest);
     if things_str.len() != 2 {
         return Err(AssemblerError::InvalidNoOfArgs(2, things_str.len()));
     }
     let reg = register_from_str(things_str[0])
         .ok_or_else(|| AssemblerError::UnknownRegister(String::from(things_str[0])))?;
-    let (sign, num_str) = parse_sign(things_str[1]);
-    let (radix, num_str) = parse_radix(num_str);
-    let val = T::from_str_radix(num_str, radix)
-        .map_err(|_| AssemblerError::InvalidNumber(String::from(num_str)))?;
-    Ok((reg, sign.to_sign(val)))
+    Ok((reg, things_str[1]))
+}
+
+fn parse_register_and_immediate(rest: &str, constants: &Constants) -> AssemblerResult<(u8, u16)> {
+    let (reg, imm_str) = parse_register_and_operand(rest)?;
+    Ok((reg, parse_immediate(imm_str, IMM_BITS, constants)? as u16))
 }
 
-fn parse_radix(num: &str) -> (u32, &str) {
+fn parse_register_and_shamt(rest: &str, constants: &Constants) -> AssemblerResult<(u8, u8)> {
+    let (reg, sh_str) = parse_register_and_operand(rest)?;
+    Ok((reg, parse_shamt(sh_str, constants)?))
+}
+
+/// Largest shift amount that fits the 5-bit field.
+const MAX_SHAMT: u8 = 31;
+
+/// Parses a shift amount, which is unsigned and at most `MAX_SHAMT`.
+fn parse_shamt(sh_str: &str, constants: &Constants) -> AssemblerResult<u8> {
+    let sh = u8::try_from(parse_value(sh_str, constants)?)
+        .map_err(|_| AssemblerError::InvalidNumber(String::from(sh_str)))?;
+    if sh > MAX_SHAMT {
+        return Err(AssemblerError::ShiftAmountOutOfRange(sh));
+    }
+    Ok(sh)
+}
+
+/// Width of the immediate field of `addi`, `compi`, `lw` and `sw`.
+const IMM_BITS: u32 = 16;
+
+/// Parses an immediate as a signed value and returns its two's-complement
+/// bit pattern in a `bits`-wide field. Both readings of the field are
+/// accepted, so for 16 bits anything from `-0x8000` to `0xFFFF` fits.
+fn parse_immediate(imm_str: &str, bits: u32, constants: &Constants) -> AssemblerResult<u32> {
+    let value = parse_value(imm_str, constants)?;
+    let min = -(1 << (bits - 1));
+    let max = (1 << bits) - 1;
+    if !(min..=max).contains(&value) {
+        return Err(AssemblerError::ImmediateOutOfRange {
+            value: String::from(imm_str),
+            bits,
+        });
+    }
+    Ok((value & max) as u32)
+}
+
+/// Named values defined with `.equ`, usable wherever an immediate is.
+pub type Constants = HashMap<String, i64>;
+
+/// Evaluates an immediate operand: a number, a constant name or an
+/// arithmetic expression over them.
+pub fn parse_value(value_str: &str, constants: &Constants) -> AssemblerResult<i64> {
+    expr::evaluate(value_str, constants)
+}
+
+pub(crate) fn parse_radix(num: &str) -> (u32, &str) {
     if num.len() < 2 {
         (10, num)
     } else {
@@ -385,58 +757,79 @@ fn parse_radix(num: &str) -> (u32, &str) {
     }
 }
 
-enum Sign {
-    Positive,
-    Negative,
-}
-
-impl Sign {
-    fn to_sign<T>(&self, val: T) -> T
-    where
-        T: AsPrimitive<i32>,
-        i32: AsPrimitive<T>,
-    {
-        let signed_val: i32 = val.as_();
-        let after_sign = match self {
-            Sign::Positive => signed_val,
-            Sign::Negative => -signed_val,
-        };
-        after_sign.as_()
-    }
-}
-
-fn parse_sign(num: &str) -> (Sign, &str) {
-    let num_bytes = num.as_bytes();
-    if num_bytes[0] == b'+' {
-        (Sign::Positive, &num[1..])
-    } else if num_bytes[0] == b'-' {
-        (Sign::Negative, &num[1..])
-    } else {
-        (Sign::Positive, num)
-    }
-}
-
-fn parse_mem_access(rest: &str) -> AssemblerResult<(u8, u16, u8)> {
+fn parse_mem_access(rest: &str, constants: &Constants) -> AssemblerResult<(u8, u16, u8)> {
     lazy_static! {
         static ref RE: Regex =
-            Regex::new(r"(\$[a-z0-9]{2}) *, *([^(]+)\((\$[a-z0-9]{2})\)").unwrap();
+            Regex::new(r"(\$[a-zA-Z0-9]+) *, *(.+)\((\$[a-zA-Z0-9]+)\) *$").unwrap();
     }
     let caps = RE
         .captures(rest)
         .ok_or_else(|| AssemblerError::InvalidInstruction(String::from(rest)))?;
     let rt = register_from_str(&caps[1])
         .ok_or_else(|| AssemblerError::UnknownRegister(String::from(&caps[1])))?;
-    let (sign, num_str) = parse_sign(&caps[2]);
-    let (radix, num_str) = parse_radix(num_str);
-    let imm = u16::from_str_radix(num_str, radix)
-        .map_err(|_| AssemblerError::InvalidNumber(String::from(num_str)))?;
+    let imm = parse_immediate(&caps[2], IMM_BITS, constants)? as u16;
     let rs = register_from_str(&caps[3])
         .ok_or_else(|| AssemblerError::UnknownRegister(String::from(&caps[3])))?;
-    Ok((rt, sign.to_sign(imm), rs))
+    Ok((rt, imm, rs))
+}
+
+fn parse_single_operand(rest: &str) -> AssemblerResult<&str> {
+    let things_str = split_operands(rest);
+    if things_str.len() != 1 || things_str[0].is_empty() {
+        return Err(AssemblerError::InvalidNoOfArgs(1, things_str.len()));
+    }
+    Ok(things_str[0])
+}
+
+fn parse_single_label(rest: &str) -> AssemblerResult<&str> {
+    parse_single_operand(rest)
+}
+
+/// Parses `label`, or `label + offset` with a byte offset expression,
+/// for the absolute branches.
+fn parse_abs_label(rest: &str, constants: &Constants) -> AssemblerResult<AbsLabel> {
+    let operand = parse_single_label(rest)?;
+    let Some(split) = operand.find(['+', '-']) else {
+        return Ok(AbsLabel::from(operand));
+    };
+    let offset_str = &operand[split..];
+    let offset = i32::try_from(parse_value(offset_str, constants)?).map_err(|_| {
+        AssemblerError::ImmediateOutOfRange {
+            value: String::from(offset_str),
+            bits: 32,
+        }
+    })?;
+    if offset % 4 != 0 {
+        return Err(AssemblerError::UnalignedOffset(String::from(offset_str)));
+    }
+    Ok(AbsLabel {
+        offset,
+        ..AbsLabel::from(operand[..split].trim_end())
+    })
+}
+
+/// Parses one `.word` operand: a value, or failing that a label with an
+/// optional offset, resolved later to its address.
+fn parse_data_word(operand: &str, constants: &Constants) -> AssemblerResult<Instruction> {
+    match parse_immediate(operand, 32, constants) {
+        Ok(value) => Ok(Instruction::Data { value }),
+        Err(err) => {
+            if !operand.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.') {
+                return Err(err);
+            }
+            let label = parse_abs_label(operand, constants)?;
+            Ok(Instruction::Address { label })
+        }
+    }
+}
+
+fn parse_single_register(rest: &str) -> AssemblerResult<u8> {
+    let reg_str = parse_single_operand(rest)?;
+    register_from_str(reg_str).ok_or_else(|| AssemblerError::UnknownRegister(String::from(reg_str)))
 }
 
-fn parse_reg_label(rest: &str) -> AssemblerResult<(u8, RelLabel)> {
-    let things_str: Vec<_> = rest.split(',').map(|x| x.trim()).collect();
+fn parse_register_and_label(rest: &str) -> AssemblerResult<(u8, RelLabel)> {
+    let things_str = split_operands(rest);
     if things_str.len() != 2 {
         return Err(AssemblerError::InvalidNoOfArgs(2, things_str.len()));
     }
@@ -446,73 +839,159 @@ fn parse_reg_label(rest: &str) -> AssemblerResult<(u8, RelLabel)> {
     Ok((reg, label))
 }
 
+const REGISTER_NAMES: [&str; 32] = [
+    "$zero", "$at", "$v0", "$v1", "$a0", "$a1", "$a2", "$a3", "$t0", "$t1", "$t2", "$t3", "$t4",
+    "$t5", "$t6", "$t7", "$s0", "$s1", "$s2", "$s3", "$s4", "$s5", "$s6", "$s7", "$t8", "$t9",
+    "$k0", "$k1", "$gp", "$sp", "$fp", "$ra",
+];
+
+/// Accepts an ABI alias such as `$t0`, in any case, or a raw number
+/// `$0` to `$31`.
 fn register_from_str(reg: &str) -> Option<u8> {
-    match reg {
-        "$zero" => Some(0),
-        "$at" => Some(1),
-        "$v0" => Some(2),
-        "$v1" => Some(3),
-        "$a0" => Some(4),
-        "$a1" => Some(5),
-        "$a2" => Some(6),
-        "$a3" => Some(7),
-        "$t0" => Some(8),
-        "$t1" => Some(9),
-        "$t2" => Some(10),
-        "$t3" => Some(11),
-        "$t4" => Some(12),
-        "$t5" => Some(13),
-        "$t6" => Some(14),
-        "$t7" => Some(15),
-        "$s0" => Some(16),
-        "$s1" => Some(17),
-        "$s2" => Some(18),
-        "$s3" => Some(19),
-        "$s4" => Some(20),
-        "$s5" => Some(21),
-        "$s6" => Some(22),
-        "$s7" => Some(23),
-        "$t8" => Some(24),
-        "$t9" => Some(25),
-        "$k0" => Some(26),
-        "$k1" => Some(27),
-        "$gp" => Some(28),
-        "$sp" => Some(29),
-        "$fp" => Some(30),
-        "$ra" => Some(31),
-        _ => None,
+    if let Some(num) = reg.strip_prefix('$') {
+        if !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()) {
+            return num.parse().ok().filter(|&num| num < 32);
+        }
     }
+    let reg = reg.to_ascii_lowercase();
+    REGISTER_NAMES
+        .iter()
+        .position(|&name| name == reg)
+        .map(|idx| idx as u8)
 }
 
-fn extract_command(instr: &str) -> Option<(&str, &str)> {
-    let blank_idx = match instr.find(' ') {
-        Some(idx) => idx,
-        None => return None,
+pub fn register_name(reg: u8) -> &'static str {
+    REGISTER_NAMES[reg as usize]
+}
+
+/// Whether `name` is a register, written with or without its `$`.
+pub fn is_register_name(name: &str) -> bool {
+    let name = name.strip_prefix('$').unwrap_or(name);
+    REGISTER_NAMES.iter().any(|reg| reg[1..] == *name)
+}
+
+const PSEUDO_INSTRUCTIONS: &[&str] = &["push", "pop", "mov", "move", "nop", "li", "li64"];
+
+/// Whether `name` is a machine or pseudo-instruction mnemonic.
+pub fn is_mnemonic(name: &str) -> bool {
+    isa::lookup(name).is_some() || PSEUDO_INSTRUCTIONS.contains(&name)
+}
+
+/// Returns the mnemonic if the line is a pseudo-instruction,
+/// i.e. one that `Instruction::from_str` expands.
+pub fn pseudo_mnemonic(instr: &str) -> Option<&str> {
+    let (comm, _) = extract_command(instr)?;
+    PSEUDO_INSTRUCTIONS
+        .contains(&comm.to_ascii_lowercase().as_str())
+        .then_some(comm)
+}
+
+/// Parses an unsigned address such as `0x100`, for directives.
+pub fn parse_address(addr_str: &str) -> AssemblerResult<u32> {
+    let (radix, num_str) = parse_radix(addr_str);
+    u32::from_str_radix(num_str, radix)
+        .map_err(|_| AssemblerError::InvalidNumber(String::from(addr_str)))
+}
+
+/// Checks that an instruction is spelled in the canonical
+/// `mnemonic op1, op2` style, for courses that enforce one.
+pub fn check_canonical_format(instr: &str) -> AssemblerResult<()> {
+    let instr = instr.trim();
+    let expected = match extract_command(instr) {
+        Some((comm, rest)) => match split_operands(rest)[..] {
+            [] => String::from(comm),
+            ref operands => format!("{} {}", comm, operands.join(", ")),
+        },
+        None => String::from(instr),
     };
-    Some((&instr[0..blank_idx], &instr[blank_idx + 1..]))
+    if expected == instr {
+        Ok(())
+    } else {
+        Err(AssemblerError::NonCanonicalFormat {
+            found: String::from(instr),
+            expected,
+        })
+    }
+}
+
+/// Splits off the mnemonic. A line without operands yields an empty
+/// `rest`, so only an empty line has no command at all.
+fn extract_command(instr: &str) -> Option<(&str, &str)> {
+    if instr.is_empty() {
+        return None;
+    }
+    match instr.find(' ') {
+        Some(blank_idx) => Some((&instr[0..blank_idx], &instr[blank_idx + 1..])),
+        None => Some((instr, "")),
+    }
+}
+
+/// Splits comma-separated operands; a blank `rest` has none.
+fn split_operands(rest: &str) -> Vec<&str> {
+    if rest.trim().is_empty() {
+        return Vec::new();
+    }
+    rest.split(',').map(|x| x.trim()).collect()
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct AbsLabel {
     name: String,
+    // Byte offset added to the label's address
+    offset: i32,
     addr: Option<u32>,
 }
 
+impl AbsLabel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    pub fn addr(&self) -> Option<u32> {
+        self.addr
+    }
+}
+
 impl From<&str> for AbsLabel {
     fn from(s: &str) -> Self {
         Self {
             name: String::from(s),
+            offset: 0,
             addr: None,
         }
     }
 }
 
+impl fmt::Display for AbsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            0 => write!(f, "{}", self.name),
+            offset if offset < 0 => write!(f, "{} - {}", self.name, offset.unsigned_abs()),
+            offset => write!(f, "{} + {}", self.name, offset),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct RelLabel {
     name: String,
     addr: Option<u16>,
 }
 
+impl RelLabel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn addr(&self) -> Option<u16> {
+        self.addr
+    }
+}
+
 impl From<&str> for RelLabel {
     fn from(s: &str) -> Self {
         Self {
@@ -534,6 +1013,244 @@ mod test {
         assert_eq!(parsed_instr.unwrap(), Instruction::Add { rs: 10, rt: 4 });
     }
 
+    #[test]
+    fn test_numeric_registers() {
+        assert_eq!(register_from_str("$0"), Some(0));
+        assert_eq!(register_from_str("$8"), Some(8));
+        assert_eq!(register_from_str("$31"), Some(31));
+        assert_eq!(register_from_str("$32"), None);
+        assert_eq!(register_from_str("$-1"), None);
+        assert_eq!(register_from_str("$x"), None);
+        assert_eq!(register_from_str("$"), None);
+        let parsed_instr = Instruction::try_from("lw $8, 4($29)");
+        assert!(parsed_instr.is_ok());
+        assert_eq!(
+            parsed_instr.unwrap(),
+            Instruction::Lw {
+                rt: 8,
+                imm: 4,
+                rs: 29
+            }
+        );
+        assert!(matches!(
+            Instruction::try_from("add $32, $t0"),
+            Err(AssemblerError::UnknownRegister(reg)) if reg == "$32"
+        ));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let upper = Instruction::try_from("ADD $T0, $A0");
+        assert!(upper.is_ok());
+        assert_eq!(
+            upper.unwrap(),
+            Instruction::try_from("add $t0, $a0").unwrap()
+        );
+        let mixed = Instruction::try_from("Addi $T0, 0x1F");
+        assert!(mixed.is_ok());
+        assert_eq!(mixed.unwrap(), Instruction::AddImm { rs: 8, imm: 31 });
+        let branch = Instruction::try_from("B Loop");
+        assert!(branch.is_ok());
+        assert_eq!(branch.unwrap().get_label_name(), "Loop");
+        let pushed = Instruction::from_str("PUSH $RA");
+        assert!(pushed.is_ok());
+        assert_eq!(pushed.unwrap(), Instruction::from_str("push $ra").unwrap());
+        assert_eq!(pseudo_mnemonic("Pop $t0"), Some("Pop"));
+    }
+
+    #[test]
+    fn test_opcode_missing() {
+        assert!(matches!(
+            Instruction::from_str(""),
+            Err(AssemblerError::OpcodeMissing(line)) if line.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_mnemonic_only() {
+        assert!(matches!(
+            Instruction::try_from("addi"),
+            Err(AssemblerError::InvalidNoOfArgs(2, 0))
+        ));
+        assert!(matches!(
+            Instruction::try_from("br"),
+            Err(AssemblerError::InvalidNoOfArgs(1, 0))
+        ));
+        assert!(matches!(
+            Instruction::try_from("halt"),
+            Err(AssemblerError::UnknownInstruction(comm)) if comm == "halt"
+        ));
+        assert_eq!(extract_command("br"), Some(("br", "")));
+        assert!(check_canonical_format("br").is_ok());
+    }
+
+    #[test]
+    fn test_nop() {
+        let nop = Instruction::from_str("nop");
+        assert!(nop.is_ok());
+        let nop = nop.unwrap();
+        assert_eq!(nop, vec![Instruction::NOP]);
+        let nop_word = nop[0].encode();
+        assert!(nop_word.is_ok());
+        assert_eq!(nop_word.unwrap(), 0x00000000);
+        assert!(matches!(
+            Instruction::try_from("nop $t0"),
+            Err(AssemblerError::InvalidNoOfArgs(0, 1))
+        ));
+        assert_eq!(pseudo_mnemonic("nop"), Some("nop"));
+    }
+
+    #[test]
+    fn test_li() {
+        let li = Instruction::from_str("li $t0, 0x1234");
+        assert!(li.is_ok());
+        assert_eq!(
+            li.unwrap(),
+            vec![
+                Instruction::Xor { rs: 8, rt: 8 },
+                Instruction::AddImm { rs: 8, imm: 0x1234 }
+            ]
+        );
+        let neg = Instruction::from_str("li $t0, -1");
+        assert!(neg.is_ok());
+        assert_eq!(neg.unwrap()[1], Instruction::AddImm { rs: 8, imm: 0xFFFF });
+        assert!(matches!(
+            Instruction::from_str("li $t0, 0x10000"),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn test_li64() {
+        let wide = Instruction::from_str("li64 $t0, $t1, 0x123456789ABCDEF0");
+        assert!(wide.is_ok());
+        assert_eq!(
+            wide.unwrap(),
+            vec![
+                Instruction::Xor { rs: 8, rt: 8 },
+                Instruction::AddImm { rs: 8, imm: 0x1234 },
+                Instruction::Sll { rs: 8, sh: 16 },
+                Instruction::AddImm { rs: 8, imm: 0x5678 },
+                Instruction::Xor { rs: 9, rt: 9 },
+                // 0xDEF0 sign-extends to -0x2110, so the upper half rounds up
+                Instruction::AddImm { rs: 9, imm: 0x9ABD },
+                Instruction::Sll { rs: 9, sh: 16 },
+                Instruction::AddImm { rs: 9, imm: 0xDEF0 },
+            ]
+        );
+        let neg = Instruction::from_str("li64 $t0, $t1, -1");
+        assert!(neg.is_ok());
+        let neg = neg.unwrap();
+        assert_eq!(neg[1], Instruction::AddImm { rs: 8, imm: 0 });
+        assert_eq!(neg[3], Instruction::AddImm { rs: 8, imm: 0xFFFF });
+        assert!(matches!(
+            Instruction::from_str("li64 $t0, 0x1"),
+            Err(AssemblerError::InvalidNoOfArgs(3, 2))
+        ));
+        assert!(Instruction::from_str("li64 $t0, $t0, 0x1").is_err());
+    }
+
+    #[test]
+    fn test_move() {
+        let moved = Instruction::from_str("move $t0, $t1");
+        assert!(moved.is_ok());
+        let moved = moved.unwrap();
+        assert_eq!(moved, Instruction::from_str("mov $t0, $t1").unwrap());
+        let words: Vec<_> = moved.iter().map(|instr| instr.encode().unwrap()).collect();
+        assert_eq!(words, vec![0x15080000, 0x01090000]);
+        assert!(matches!(
+            Instruction::from_str("move $t0"),
+            Err(AssemblerError::InvalidNoOfArgs(2, 1))
+        ));
+    }
+
+    #[test]
+    fn test_from_str_expansion() {
+        let real = Instruction::from_str("sw $t0, 4($sp)");
+        assert!(real.is_ok());
+        assert_eq!(
+            real.unwrap(),
+            vec![Instruction::try_from("sw $t0, 4($sp)").unwrap()]
+        );
+        let pseudo = Instruction::from_str("push $t0");
+        assert!(pseudo.is_ok());
+        assert_eq!(
+            pseudo.unwrap(),
+            vec![
+                Instruction::Sw {
+                    rt: 8,
+                    rs: 29,
+                    imm: 0
+                },
+                Instruction::AddImm {
+                    rs: 29,
+                    imm: 0xFFFC
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_words() {
+        let words = Instruction::from_str(".word 0x1, -1, 7");
+        assert!(words.is_ok());
+        let words = words.unwrap();
+        assert_eq!(
+            words,
+            vec![
+                Instruction::Data { value: 1 },
+                Instruction::Data { value: 0xFFFFFFFF },
+                Instruction::Data { value: 7 }
+            ]
+        );
+        assert_eq!(words[1].encode().unwrap(), 0xFFFFFFFF);
+        assert_eq!(words[0].to_string(), ".word 0x00000001");
+        assert!(matches!(
+            Instruction::from_str(".word"),
+            Err(AssemblerError::InvalidNoOfArgs(1, 0))
+        ));
+        let mixed = Instruction::from_str(".word case0, 4, case1 + 8");
+        assert!(mixed.is_ok());
+        let mixed = mixed.unwrap();
+        assert_eq!(mixed[1], Instruction::Data { value: 4 });
+        assert_eq!(mixed[2].get_label_name(), "case1");
+        assert_eq!(mixed[2].to_string(), ".word case1 + 8");
+        assert!(mixed[0].has_label() && !mixed[0].has_abs_label());
+        assert!(matches!(
+            mixed[0].encode(),
+            Err(AssemblerError::FloatingLabel(name)) if name == "case0"
+        ));
+    }
+
+    #[test]
+    fn test_expression_operands() {
+        let constants = Constants::from([(String::from("BASE"), 0x10)]);
+        let parse = |instr| Instruction::from_str_with(instr, &constants);
+        let summed = parse("addi $t0, BASE + 4");
+        assert!(summed.is_ok());
+        assert_eq!(
+            summed.unwrap(),
+            vec![Instruction::AddImm { rs: 8, imm: 0x14 }]
+        );
+        let shifted = parse("sll $t0, 1 << 3");
+        assert!(shifted.is_ok());
+        assert_eq!(shifted.unwrap(), vec![Instruction::Sll { rs: 8, sh: 8 }]);
+        let offset = parse("lw $t0, (BASE - 1) * 4($sp)");
+        assert!(offset.is_ok());
+        assert_eq!(
+            offset.unwrap(),
+            vec![Instruction::Lw {
+                rt: 8,
+                imm: 60,
+                rs: 29
+            }]
+        );
+        assert!(matches!(
+            parse("addi $t0, BASE << 12"),
+            Err(AssemblerError::ImmediateOutOfRange { .. })
+        ));
+    }
+
     #[test]
     fn test_imm_instr() {
         let instr = "compi $t2   ,  0x20";
@@ -559,6 +1276,63 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_immediate_range() {
+        let widest = Instruction::try_from("addi $t0, 0xFFFF");
+        assert!(widest.is_ok());
+        assert_eq!(widest.unwrap(), Instruction::AddImm { rs: 8, imm: 0xFFFF });
+        assert!(matches!(
+            Instruction::try_from("addi $t0, 65536"),
+            Err(AssemblerError::ImmediateOutOfRange { value, bits: 16 }) if value == "65536"
+        ));
+        assert!(matches!(
+            Instruction::try_from("compi $t0, 70000"),
+            Err(AssemblerError::ImmediateOutOfRange { .. })
+        ));
+        assert!(matches!(
+            Instruction::try_from("addi $t0, 0x1ffffffffffffffff"),
+            Err(AssemblerError::ImmediateOutOfRange { .. })
+        ));
+        assert!(matches!(
+            Instruction::try_from("lw $t0, 0x10000($sp)"),
+            Err(AssemblerError::ImmediateOutOfRange { .. })
+        ));
+        assert!(matches!(
+            Instruction::try_from("addi $t0, 12ab"),
+            Err(AssemblerError::InvalidNumber(_))
+        ));
+    }
+
+    #[test]
+    fn test_neg_imm_encoding() {
+        let all_ones = Instruction::try_from("addi $t0, -1");
+        assert!(all_ones.is_ok());
+        let all_ones_word = all_ones.unwrap().encode();
+        assert!(all_ones_word.is_ok());
+        assert_eq!(all_ones_word.unwrap() & 0xFFFF, 0xFFFF);
+        let lowest = Instruction::try_from("compi $t0, -0x8000");
+        assert!(lowest.is_ok());
+        assert_eq!(lowest.unwrap(), Instruction::CompImm { rs: 8, imm: 0x8000 });
+        let offset = Instruction::try_from("lw $t0, -4($sp)");
+        assert!(offset.is_ok());
+        assert_eq!(
+            offset.unwrap(),
+            Instruction::Lw {
+                rt: 8,
+                imm: 0xFFFC,
+                rs: 29
+            }
+        );
+        assert!(matches!(
+            Instruction::try_from("addi $t0, -32769"),
+            Err(AssemblerError::ImmediateOutOfRange { value, bits: 16 }) if value == "-32769"
+        ));
+        assert!(matches!(
+            Instruction::try_from("addi $t0, --1"),
+            Err(AssemblerError::InvalidExpression(_))
+        ));
+    }
+
     #[test]
     fn test_shamt_instr() {
         let instr = "sll $t2  ,   3";
@@ -567,6 +1341,25 @@ mod test {
         assert_eq!(parsed_instr.unwrap(), Instruction::Sll { rs: 10, sh: 3 });
     }
 
+    #[test]
+    fn test_shamt_range() {
+        let widest = Instruction::try_from("sll $t0, 31");
+        assert!(widest.is_ok());
+        assert_eq!(widest.unwrap(), Instruction::Sll { rs: 8, sh: 31 });
+        assert!(matches!(
+            Instruction::try_from("sll $t0, 32"),
+            Err(AssemblerError::ShiftAmountOutOfRange(32))
+        ));
+        assert!(matches!(
+            Instruction::try_from("sra $t0, 0x28"),
+            Err(AssemblerError::ShiftAmountOutOfRange(40))
+        ));
+        assert!(matches!(
+            Instruction::try_from("srl $t0, -1"),
+            Err(AssemblerError::InvalidNumber(sh)) if sh == "-1"
+        ));
+    }
+
     #[test]
     fn test_mem_instr() {
         let load = "lw $t1,  16($t2)";
@@ -603,10 +1396,24 @@ mod test {
             Instruction::B {
                 label: AbsLabel {
                     name: String::from("Hell"),
+                    offset: 0,
                     addr: None
                 }
             }
         );
+        let offset_jmp = Instruction::try_from("b table+8");
+        assert!(offset_jmp.is_ok());
+        let offset_jmp = offset_jmp.unwrap();
+        assert_eq!(offset_jmp.get_label_name(), "table");
+        assert_eq!(offset_jmp.abs_label().map(AbsLabel::offset), Some(8));
+        assert_eq!(offset_jmp.to_string(), "b table + 8");
+        let back_jmp = Instruction::try_from("bl table - 4");
+        assert!(back_jmp.is_ok());
+        assert_eq!(back_jmp.unwrap().to_string(), "bl table - 4");
+        assert!(matches!(
+            Instruction::try_from("b table + 3"),
+            Err(AssemblerError::UnalignedOffset(offset)) if offset == "+ 3"
+        ));
         let cond_jmp = "bltz $s0, Else1";
         let cond_jmp_instr = Instruction::try_from(cond_jmp);
         assert!(cond_jmp_instr.is_ok());
@@ -622,6 +1429,74 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_control_flow_forms() {
+        let lines = [
+            "b Loop",
+            "bl fibo",
+            "br $ra",
+            "bltz $t0, Exit",
+            "bz $t0, Exit",
+            "bnz $t0, Loop",
+            "bcy Overflow",
+            "bncy Loop",
+        ];
+        for line in lines {
+            let instr = Instruction::try_from(line);
+            assert!(instr.is_ok());
+            assert_eq!(instr.unwrap().to_string(), line);
+        }
+        let padded = Instruction::try_from("br   $ra ");
+        assert!(padded.is_ok());
+        assert_eq!(padded.unwrap(), Instruction::Br { rs: 31 });
+        assert!(matches!(
+            Instruction::try_from("b Loop, Exit"),
+            Err(AssemblerError::InvalidNoOfArgs(1, 2))
+        ));
+        assert!(matches!(
+            Instruction::try_from("bz Exit"),
+            Err(AssemblerError::InvalidNoOfArgs(2, 1))
+        ));
+        assert!(matches!(
+            Instruction::try_from("br $xx"),
+            Err(AssemblerError::UnknownRegister(_))
+        ));
+    }
+
+    #[test]
+    fn test_label_helpers() {
+        let mut bl_instr = Instruction::try_from("bl fibo").unwrap();
+        assert!(bl_instr.has_abs_label() && !bl_instr.has_rel_label());
+        assert_eq!(bl_instr.get_label_name(), "fibo");
+        bl_instr.set_abs_addr(0x20);
+        assert_eq!(bl_instr.abs_label().and_then(AbsLabel::addr), Some(0x20));
+        let mut bcy_instr = Instruction::try_from("bcy Overflow").unwrap();
+        assert!(bcy_instr.has_rel_label() && !bcy_instr.has_abs_label());
+        assert_eq!(bcy_instr.get_label_name(), "Overflow");
+        bcy_instr.set_rel_addr(3);
+        assert_eq!(bcy_instr.rel_label().and_then(RelLabel::addr), Some(3));
+        // Setting an address on the wrong kind of instruction leaves it alone
+        let mut add_instr = Instruction::Add { rs: 8, rt: 9 };
+        add_instr.set_abs_addr(0x20);
+        add_instr.set_rel_addr(3);
+        assert_eq!(add_instr, Instruction::Add { rs: 8, rt: 9 });
+        bl_instr.set_rel_addr(3);
+        assert_eq!(bl_instr.abs_label().and_then(AbsLabel::addr), Some(0x20));
+    }
+
+    #[test]
+    fn test_jtype_addr_range() {
+        let mut b_instr = Instruction::try_from("b far").unwrap();
+        b_instr.set_abs_addr(0x3FFFFFC);
+        assert_eq!(b_instr.encode().unwrap(), 0x3BFFFFFC);
+        // Would spill into the opcode and decode as `br`
+        b_instr.set_abs_addr(0x4000000);
+        assert!(matches!(
+            b_instr.encode(),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 26, .. })
+        ));
+    }
+
     #[test]
     fn test_encoding() {
         let and_instr = Instruction::And { rs: 10, rt: 23 };
@@ -643,6 +1518,7 @@ mod test {
         let b_instr = Instruction::B {
             label: AbsLabel {
                 name: String::from("L0"),
+                offset: 0,
                 addr: Some(0xA7FFF),
             },
         };
@@ -650,4 +1526,119 @@ mod test {
         assert!(b_instr_word.is_ok());
         assert_eq!(b_instr_word.unwrap(), 0x380A7FFF);
     }
+
+    #[test]
+    fn test_encoding_fields() {
+        let add_instr = Instruction::Add { rs: 10, rt: 23 };
+        let add_instr_word = add_instr.encode();
+        assert!(add_instr_word.is_ok());
+        assert_eq!(add_instr_word.unwrap(), 0x01570000);
+        let sll_instr = Instruction::Sll { rs: 8, sh: 2 };
+        let sll_instr_word = sll_instr.encode();
+        assert!(sll_instr_word.is_ok());
+        assert_eq!(sll_instr_word.unwrap(), 0x19020000);
+        let sw_instr = Instruction::Sw {
+            rs: 29,
+            rt: 31,
+            imm: 0xFFFC,
+        };
+        let sw_instr_word = sw_instr.encode();
+        assert!(sw_instr_word.is_ok());
+        assert_eq!(sw_instr_word.unwrap(), 0x37BFFFFC);
+        let bz_instr = Instruction::Bz {
+            rs: 8,
+            label: RelLabel {
+                name: String::from("Loop"),
+                addr: Some(0xFFFE),
+            },
+        };
+        let bz_instr_word = bz_instr.encode();
+        assert!(bz_instr_word.is_ok());
+        assert_eq!(bz_instr_word.unwrap(), 0x4500FFFE);
+        let floating = [
+            Instruction::Bl {
+                label: AbsLabel::from("fibo"),
+            },
+            Instruction::Bncy {
+                label: RelLabel::from("Loop"),
+            },
+        ];
+        for instr in floating {
+            assert!(matches!(
+                instr.encode(),
+                Err(AssemblerError::FloatingLabel(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_word_width() {
+        let add_instr = Instruction::AddImm { rs: 10, imm: 657 };
+        let full_word = add_instr.encode_word(DEFAULT_WORD_WIDTH);
+        assert!(full_word.is_ok());
+        assert_eq!(full_word.unwrap().value(), 0x09400291);
+        // The immediate field shrinks to 8 bits in a 24-bit word
+        let narrow_add = Instruction::AddImm { rs: 10, imm: 0x91 }.encode_word(24);
+        assert!(narrow_add.is_ok());
+        assert_eq!(narrow_add.unwrap().value(), 0x09_4091);
+        let negative = Instruction::AddImm {
+            rs: 10,
+            imm: -4_i16 as u16,
+        };
+        assert_eq!(negative.encode_word(24).unwrap().value(), 0x09_40FC);
+        assert!(matches!(
+            add_instr.encode_word(24),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 8, .. })
+        ));
+        // A 16-bit word keeps only the opcode and registers
+        let reg_word = Instruction::Add { rs: 8, rt: 9 }.encode_word(16);
+        assert!(reg_word.is_ok());
+        assert_eq!(reg_word.unwrap().value(), 0x0109);
+        assert!(matches!(
+            add_instr.encode_word(16),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 0, .. })
+        ));
+        let mut jump = Instruction::B {
+            label: AbsLabel::from("Loop"),
+        };
+        jump.set_abs_addr(0x40);
+        assert_eq!(jump.encode_word(16).unwrap().value(), 0x3840);
+        jump.set_abs_addr(0x400);
+        assert!(matches!(
+            jump.encode_word(16),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 10, .. })
+        ));
+        assert!(matches!(
+            add_instr.encode_word(12),
+            Err(AssemblerError::WordTooWide { width: 12, .. })
+        ));
+        let narrow_word = Instruction::Data { value: 0x0291 }.encode_word(16);
+        assert!(narrow_word.is_ok());
+        let narrow_word = narrow_word.unwrap();
+        assert_eq!(narrow_word.width(), 16);
+        assert_eq!(narrow_word.value(), 0x0291);
+        assert_eq!(narrow_word.to_hex(), "0291");
+        assert_eq!(Word::new(0xFFFC, 16).signed_value(), -4);
+        assert_eq!(Word::new(0x7FFF, 16).signed_value(), 0x7FFF);
+        assert_eq!(Word::new(5, DEFAULT_WORD_WIDTH).to_hex(), "00000005");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let lines = [
+            "add $t2, $a0",
+            "addi $sp, -4",
+            "sra $s1, 3",
+            "sw $ra, -8($sp)",
+            "bl fibo",
+            "br $ra",
+            "bncy Loop",
+            "bnz $t0, Loop",
+        ];
+        for line in lines {
+            let instr = Instruction::try_from(line);
+            assert!(instr.is_ok());
+            assert_eq!(instr.unwrap().to_string(), line);
+        }
+    }
 }
@@ -0,0 +1,331 @@
+use crate::error::{AssemblerError, Result as AssemblerResult};
+use std::{collections::HashMap, io::Write};
+
+/// Operand format of a machine instruction, which decides both the
+/// assembly syntax and how the operands are laid out in the word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Register,
+    Immediate,
+    Shift,
+    Memory,
+    AbsBranch,
+    RegBranch,
+    FlagBranch,
+    CondBranch,
+}
+
+impl Format {
+    const ALL: [Format; 8] = [
+        Format::Register,
+        Format::Immediate,
+        Format::Shift,
+        Format::Memory,
+        Format::AbsBranch,
+        Format::RegBranch,
+        Format::FlagBranch,
+        Format::CondBranch,
+    ];
+
+    /// Name used for the format in a dumped ISA table.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Register => "register",
+            Format::Immediate => "immediate",
+            Format::Shift => "shift",
+            Format::Memory => "memory",
+            Format::AbsBranch => "abs-branch",
+            Format::RegBranch => "reg-branch",
+            Format::FlagBranch => "flag-branch",
+            Format::CondBranch => "cond-branch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn syntax(&self) -> &'static str {
+        match self {
+            Format::Register => "rs, rt",
+            Format::Immediate => "rs, imm",
+            Format::Shift => "rs, sh",
+            Format::Memory => "rt, imm(rs)",
+            Format::AbsBranch | Format::FlagBranch => "label",
+            Format::RegBranch => "rs",
+            Format::CondBranch => "rs, label",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Format::Register => "two registers",
+            Format::Immediate => "register and 16-bit immediate",
+            Format::Shift => "register and immediate shift amount",
+            Format::Memory => "register and base register with 16-bit offset",
+            Format::AbsBranch => "label resolved to an absolute address",
+            Format::RegBranch => "register holding the target address",
+            Format::FlagBranch => "label resolved relative to PC + 4",
+            Format::CondBranch => "register and label resolved relative to PC + 4",
+        }
+    }
+
+    pub fn layout(&self) -> &'static str {
+        match self {
+            Format::Register => "op[31:26] rs[25:21] rt[20:16] 0[15:0]",
+            Format::Immediate => "op[31:26] rs[25:21] 0[20:16] imm[15:0]",
+            Format::Shift => "op[31:26] rs[25:21] sh[20:16] 0[15:0]",
+            Format::Memory => "op[31:26] rs[25:21] rt[20:16] imm[15:0]",
+            Format::AbsBranch => "op[31:26] addr[25:0]",
+            Format::RegBranch => "op[31:26] rs[25:21] 0[20:0]",
+            Format::FlagBranch => "op[31:26] 0[25:16] offset[15:0]",
+            Format::CondBranch => "op[31:26] rs[25:21] 0[20:16] offset[15:0]",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MnemonicInfo {
+    pub mnemonic: &'static str,
+    pub opcode: u8,
+    pub format: Format,
+    pub example: &'static str,
+}
+
+const fn entry(
+    mnemonic: &'static str,
+    opcode: u8,
+    format: Format,
+    example: &'static str,
+) -> MnemonicInfo {
+    MnemonicInfo {
+        mnemonic,
+        opcode,
+        format,
+        example,
+    }
+}
+
+pub const ISA: &[MnemonicInfo] = &[
+    entry("add", 0, Format::Register, "add $t0, $t1"),
+    entry("comp", 1, Format::Register, "comp $t0, $t1"),
+    entry("addi", 2, Format::Immediate, "addi $t0, 10"),
+    entry("compi", 3, Format::Immediate, "compi $t0, 10"),
+    entry("and", 4, Format::Register, "and $t0, $t1"),
+    entry("xor", 5, Format::Register, "xor $t0, $t1"),
+    entry("sll", 6, Format::Shift, "sll $t0, 2"),
+    entry("srl", 7, Format::Shift, "srl $t0, 2"),
+    entry("sllv", 8, Format::Register, "sllv $t0, $t1"),
+    entry("srlv", 9, Format::Register, "srlv $t0, $t1"),
+    entry("sra", 10, Format::Shift, "sra $t0, 2"),
+    entry("srav", 11, Format::Register, "srav $t0, $t1"),
+    entry("lw", 12, Format::Memory, "lw $t0, 4($sp)"),
+    entry("sw", 13, Format::Memory, "sw $t0, 4($sp)"),
+    entry("b", 14, Format::AbsBranch, "b Loop"),
+    entry("br", 15, Format::RegBranch, "br $ra"),
+    entry("bltz", 16, Format::CondBranch, "bltz $t0, Exit"),
+    entry("bz", 17, Format::CondBranch, "bz $t0, Exit"),
+    entry("bnz", 18, Format::CondBranch, "bnz $t0, Loop"),
+    entry("bl", 19, Format::AbsBranch, "bl fibo"),
+    entry("bcy", 20, Format::FlagBranch, "bcy Overflow"),
+    entry("bncy", 21, Format::FlagBranch, "bncy Loop"),
+];
+
+/// Describes a mnemonic's opcode and operand format, as used by the encoder.
+pub fn lookup(mnemonic: &str) -> Option<&'static MnemonicInfo> {
+    ISA.iter().find(|info| info.mnemonic == mnemonic)
+}
+
+pub fn explain(mnemonic: &str) -> AssemblerResult<String> {
+    let info = lookup(mnemonic)
+        .ok_or_else(|| AssemblerError::UnknownInstruction(String::from(mnemonic)))?;
+    Ok(format!(
+        "{} {}\n  format:   {}\n  encoding: opcode={} ({:06b}), {}\n  example:  {}\n",
+        info.mnemonic,
+        info.format.syntax(),
+        info.format.description(),
+        info.opcode,
+        info.opcode,
+        info.format.layout(),
+        info.example
+    ))
+}
+
+/// Opcode assignment for every mnemonic, either the built-in `ISA` or
+/// an edited copy loaded back from a dumped table. Operand formats are
+/// fixed by the parser, so only opcodes can be retargeted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeTable {
+    opcodes: HashMap<&'static str, u8>,
+}
+
+impl Default for OpcodeTable {
+    fn default() -> Self {
+        Self {
+            opcodes: ISA
+                .iter()
+                .map(|info| (info.mnemonic, info.opcode))
+                .collect(),
+        }
+    }
+}
+
+impl OpcodeTable {
+    pub fn opcode(&self, mnemonic: &str) -> Option<u8> {
+        self.opcodes.get(mnemonic).copied()
+    }
+
+    /// Writes the table as TOML, one `[[instruction]]` per mnemonic.
+    pub fn write<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        writeln!(
+            writer,
+            "# Opcode table used by the encoder. Opcodes may be edited;"
+        )?;
+        writeln!(
+            writer,
+            "# formats are fixed by the assembler and must not change."
+        )?;
+        for info in ISA {
+            writeln!(writer)?;
+            writeln!(writer, "[[instruction]]")?;
+            writeln!(writer, "mnemonic = \"{}\"", info.mnemonic)?;
+            writeln!(writer, "opcode = {}", self.opcodes[info.mnemonic])?;
+            writeln!(writer, "format = \"{}\"", info.format.name())?;
+        }
+        Ok(())
+    }
+
+    /// Reads back the TOML subset written by `write`. Every mnemonic
+    /// must appear exactly once with its original format.
+    pub fn parse(text: &str) -> AssemblerResult<Self> {
+        let invalid = |line: usize, msg: String| {
+            AssemblerError::InvalidIsaTable(format!("line {}: {}", line, msg))
+        };
+        let mut entries: Vec<HashMap<&str, &str>> = Vec::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "[[instruction]]" {
+                entries.push(HashMap::new());
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                invalid(
+                    line_idx + 1,
+                    format!("expected `key = value`, found `{}`", line),
+                )
+            })?;
+            let entry = entries.last_mut().ok_or_else(|| {
+                invalid(line_idx + 1, String::from("key outside `[[instruction]]`"))
+            })?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            entry.insert(key.trim(), value);
+        }
+
+        let mut opcodes = HashMap::new();
+        for entry in entries {
+            let field = |key: &str| {
+                entry.get(key).copied().ok_or_else(|| {
+                    AssemblerError::InvalidIsaTable(format!("entry missing `{}`", key))
+                })
+            };
+            let mnemonic = field("mnemonic")?;
+            let info = lookup(mnemonic)
+                .ok_or_else(|| AssemblerError::UnknownInstruction(String::from(mnemonic)))?;
+            let opcode = field("opcode")?;
+            let opcode = opcode
+                .parse::<u8>()
+                .ok()
+                .filter(|&opcode| opcode < 64)
+                .ok_or_else(|| AssemblerError::InvalidNumber(String::from(opcode)))?;
+            if Format::from_name(field("format")?) != Some(info.format) {
+                return Err(AssemblerError::InvalidIsaTable(format!(
+                    "`{}` must keep format `{}`",
+                    mnemonic,
+                    info.format.name()
+                )));
+            }
+            if opcodes.insert(info.mnemonic, opcode).is_some() {
+                return Err(AssemblerError::InvalidIsaTable(format!(
+                    "`{}` listed twice",
+                    mnemonic
+                )));
+            }
+        }
+        if let Some(missing) = ISA.iter().find(|info| !opcodes.contains_key(info.mnemonic)) {
+            return Err(AssemblerError::InvalidIsaTable(format!(
+                "`{}` missing",
+                missing.mnemonic
+            )));
+        }
+        Ok(Self { opcodes })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_explain_shift() {
+        let text = explain("sll");
+        assert!(text.is_ok());
+        let text = text.unwrap();
+        assert!(text.starts_with("sll rs, sh\n"));
+        assert!(text.contains("immediate shift amount"));
+        assert!(text.contains("opcode=6 (000110)"));
+    }
+
+    #[test]
+    fn test_lookup_memory() {
+        let info = lookup("lw");
+        assert!(info.is_some());
+        let info = info.unwrap();
+        assert_eq!(info.opcode, 12);
+        assert_eq!(info.format, Format::Memory);
+        assert_eq!(info.format.syntax(), "rt, imm(rs)");
+        assert!(lookup("push").is_none());
+    }
+
+    #[test]
+    fn test_opcode_table_round_trip() {
+        let table = OpcodeTable::default();
+        let mut dumped = Vec::new();
+        assert!(table.write(&mut dumped).is_ok());
+        let dumped = String::from_utf8(dumped).unwrap();
+        let reloaded = OpcodeTable::parse(&dumped);
+        assert!(reloaded.is_ok());
+        let reloaded = reloaded.unwrap();
+        assert_eq!(reloaded, table);
+        for info in ISA {
+            assert_eq!(reloaded.opcode(info.mnemonic), Some(info.opcode));
+        }
+
+        let edited = dumped.replace(
+            "mnemonic = \"add\"\nopcode = 0",
+            "mnemonic = \"add\"\nopcode = 42",
+        );
+        let edited = OpcodeTable::parse(&edited);
+        assert!(edited.is_ok());
+        assert_eq!(edited.unwrap().opcode("add"), Some(42));
+        let reformatted = dumped.replace("format = \"shift\"", "format = \"register\"");
+        assert!(matches!(
+            OpcodeTable::parse(&reformatted),
+            Err(AssemblerError::InvalidIsaTable(_))
+        ));
+    }
+
+    #[test]
+    fn test_explain_unknown() {
+        assert!(matches!(
+            explain("addx"),
+            Err(AssemblerError::UnknownInstruction(_))
+        ));
+    }
+}
@@ -0,0 +1,3524 @@
+use coe::CoeRadix;
+use error::Result as AssemblerResult;
+use instruction::{AbsLabel, Constants, Instruction, RelLabel, Word, DEFAULT_WORD_WIDTH};
+use isa::OpcodeTable;
+use regex::Regex;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+
+pub mod coe;
+mod crc;
+pub mod error;
+mod expr;
+pub mod image;
+pub mod instruction;
+pub mod isa;
+pub mod mif;
+
+pub use error::{AssemblerError, Result};
+
+#[macro_use]
+extern crate lazy_static;
+
+/// Directive names, without their leading `.`.
+const DIRECTIVES: &[&str] = &[
+    "entry", "global", "comment", "org", "word", "space", "align", "equ",
+];
+
+/// Turns an output file's stem into a valid C identifier.
+fn c_identifier(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let mut ident: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// Assembles a source file, decompressing it first when it is gzipped.
+pub fn assemble<P: AsRef<Path>>(
+    input: P,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<AssemblerError>> {
+    assemble_located(input, options).map_err(errors_of)
+}
+
+/// Like `assemble`, but keeps the source location of each error so it
+/// can be rendered against the text from `read_source`.
+pub fn assemble_located<P: AsRef<Path>>(
+    input: P,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<Diagnostic>> {
+    let reader = open_source(input, options).map_err(|err| vec![Diagnostic::from(err)])?;
+    assemble_reader_located(reader, options)
+}
+
+/// Assembles several source files in order as one program, so labels
+/// are shared and addresses run on from one file into the next.
+/// Diagnostic lines count through the files as concatenated by
+/// `join_sources`.
+pub fn assemble_files<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<Diagnostic>> {
+    let sources = inputs
+        .iter()
+        .map(|input| read_source(input, options))
+        .collect::<AssemblerResult<Vec<_>>>()
+        .map_err(|err| vec![Diagnostic::from(err)])?;
+    assemble_reader_located(join_sources(&sources).as_bytes(), options)
+}
+
+/// Concatenates sources, ending each with a newline so the last line of
+/// one file never runs into the first line of the next.
+pub fn join_sources<S: AsRef<str>>(sources: &[S]) -> String {
+    let mut joined = String::new();
+    for source in sources {
+        let source = source.as_ref();
+        joined.push_str(source);
+        if !source.is_empty() && !source.ends_with('\n') {
+            joined.push('\n');
+        }
+    }
+    joined
+}
+
+/// Reads the whole source file, decompressing it like `assemble` does.
+pub fn read_source<P: AsRef<Path>>(input: P, options: &AssembleOptions) -> AssemblerResult<String> {
+    let mut source = String::new();
+    open_source(input, options)?.read_to_string(&mut source)?;
+    Ok(source)
+}
+
+fn open_source<P: AsRef<Path>>(
+    input: P,
+    options: &AssembleOptions,
+) -> AssemblerResult<Box<dyn BufRead>> {
+    let is_gzip = options.gzip || input.as_ref().extension().is_some_and(|ext| ext == "gz");
+    let file = File::open(input)?;
+    if is_gzip {
+        gzip_reader(file)
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+#[cfg(feature = "flate2")]
+fn gzip_reader(file: File) -> AssemblerResult<Box<dyn BufRead>> {
+    Ok(Box::new(BufReader::new(flate2::read::GzDecoder::new(file))))
+}
+
+#[cfg(not(feature = "flate2"))]
+fn gzip_reader(_file: File) -> AssemblerResult<Box<dyn BufRead>> {
+    Err(AssemblerError::GzipUnsupported)
+}
+
+/// Assembles a program held in memory with the default options and
+/// returns the encoded words, reporting the first error found.
+pub fn assemble_str(source: &str) -> AssemblerResult<Vec<u32>> {
+    let mut parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default())?;
+    if let Some(err) = parsed_asm.check_labels().into_iter().next() {
+        return Err(err);
+    }
+    parsed_asm.assign_labels(0)?;
+    parsed_asm.instrs.iter().map(Instruction::encode).collect()
+}
+
+/// Parses, checks and resolves a whole program held in memory.
+pub fn assemble_with_options(
+    source: &str,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<AssemblerError>> {
+    assemble_reader(source.as_bytes(), options)
+}
+
+pub fn assemble_reader<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<AssemblerError>> {
+    assemble_reader_located(reader, options).map_err(errors_of)
+}
+
+pub fn assemble_reader_located<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Vec<Diagnostic>> {
+    let mut parsed_asm = if options.fail_fast {
+        parse_reader_located(reader, options).map_err(|diag| vec![diag])?
+    } else {
+        let (parsed_asm, mut diags) = parse_lenient(reader, options);
+        if !diags.is_empty() {
+            diags.extend(parsed_asm.label_diagnostics());
+            return Err(diags);
+        }
+        parsed_asm
+    };
+    let mut label_errs = parsed_asm.label_diagnostics();
+    label_errs.extend(parsed_asm.shadowing_diagnostics());
+    if !label_errs.is_empty() {
+        return Err(label_errs);
+    }
+    parsed_asm.apply_hazard_fixes().map_err(|diag| vec![diag])?;
+    parsed_asm
+        .assign_labels_located(parsed_asm.options.base_addr)
+        .map_err(|diag| vec![diag])?;
+    parsed_asm
+        .verify_crc()
+        .map_err(|err| vec![Diagnostic::from(err)])?;
+    Ok(parsed_asm)
+}
+
+fn errors_of(diagnostics: Vec<Diagnostic>) -> Vec<AssemblerError> {
+    diagnostics.into_iter().map(|diag| diag.error).collect()
+}
+
+/// Opt-in header before raw binary images, so a loader can check what
+/// it was given: these magic bytes, then the format version and the word
+/// count as big-endian `u32`s, then the source name, truncated and
+/// NUL-padded to `BIN_HEADER_NAME_BYTES`.
+pub const BIN_HEADER_MAGIC: [u8; 4] = *b"GATH";
+pub const BIN_HEADER_VERSION: u32 = 1;
+pub const BIN_HEADER_NAME_BYTES: usize = 20;
+pub const BIN_HEADER_BYTES: usize = 12 + BIN_HEADER_NAME_BYTES;
+
+/// Streams a raw image, as `bin_bytes` lays it out, to `addr`
+/// (`host:port`), e.g. a simulator or bootloader listening on the network.
+pub fn emit_tcp(addr: &str, bytes: &[u8]) -> AssemblerResult<()> {
+    let failed = |source| AssemblerError::EmitFailed {
+        target: format!("tcp:{}", addr),
+        source,
+    };
+    let mut stream = TcpStream::connect(addr).map_err(failed)?;
+    stream.write_all(bytes).map_err(failed)?;
+    stream.flush().map_err(failed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Coe,
+    Mif,
+    ReadMem(MemRadix),
+    // Array named after the output file's stem
+    CHeader,
+    Python(PythonLiteral),
+    // Raw image bytes, as written by `write_bin`
+    Bin(Endianness),
+}
+
+/// Digit style of a Verilog `$readmemh`/`$readmemb` dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemRadix {
+    Hex,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PythonLiteral {
+    // `program = bytes.fromhex("...")`, big-endian words
+    Bytes,
+    // `program = [0x..., ...]`
+    List,
+}
+
+/// Byte order of the words in a raw binary image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl FromStr for Endianness {
+    type Err = String;
+
+    fn from_str(endian: &str) -> Result<Self, Self::Err> {
+        match endian {
+            "little" => Ok(Endianness::Little),
+            "big" => Ok(Endianness::Big),
+            _ => Err(format!(
+                "unknown byte order `{}`, expected little or big",
+                endian
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Names accepted by `from_str`, aliases included.
+    pub const NAMES: &'static [&'static str] = &[
+        "coe",
+        "mif",
+        "readmemh",
+        "hex",
+        "verilog",
+        "readmemb",
+        "binb",
+        "c-header",
+        "c",
+        "python",
+        "python-list",
+        "bin",
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Coe => "coe",
+            OutputFormat::Mif => "mif",
+            OutputFormat::ReadMem(MemRadix::Hex) => "memh",
+            OutputFormat::ReadMem(MemRadix::Binary) => "memb",
+            OutputFormat::CHeader => "h",
+            OutputFormat::Python(_) => "py",
+            OutputFormat::Bin(_) => "bin",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "coe" => Ok(OutputFormat::Coe),
+            "mif" => Ok(OutputFormat::Mif),
+            "readmemh" | "hex" | "verilog" => Ok(OutputFormat::ReadMem(MemRadix::Hex)),
+            "readmemb" | "binb" => Ok(OutputFormat::ReadMem(MemRadix::Binary)),
+            "c-header" | "c" => Ok(OutputFormat::CHeader),
+            "python" => Ok(OutputFormat::Python(PythonLiteral::Bytes)),
+            "python-list" => Ok(OutputFormat::Python(PythonLiteral::List)),
+            "bin" => Ok(OutputFormat::Bin(Endianness::Big)),
+            _ => Err(format!(
+                "unknown output format `{}`, expected one of {}",
+                format,
+                OutputFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Where an instruction came from: a 1-based line number and the
+/// byte range of the instruction text within that line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hazard {
+    pub producer: usize,
+    pub consumer: usize,
+    pub reg: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RegisterUsage {
+    pub reg: u8,
+    pub reads: usize,
+    pub writes: usize,
+}
+
+pub struct ParsedAsm {
+    pub instrs: Vec<Instruction>,
+    // Parallel to `instrs`; pseudo-instructions share their line's span
+    pub spans: Vec<SourceSpan>,
+    pub labels: HashMap<String, usize>,
+    // Where each label was defined
+    pub label_spans: HashMap<String, SourceSpan>,
+    // Label named by `.entry` or `.global`
+    pub entry: Option<String>,
+    // Notes from `.comment "..."`, keyed by the instruction they precede
+    pub comments: HashMap<usize, String>,
+    // From an `expected-crc=0x...` comment in the source
+    pub expected_crc: Option<u32>,
+    // Values from `.equ`
+    pub constants: Constants,
+    // Source text, one entry per line, for showing spans back
+    pub source: Vec<String>,
+    // Non-fatal findings, for the front-end to show
+    pub warnings: Vec<Warning>,
+    // Zero words from layout directives, in address order
+    padding: Vec<Padding>,
+    pub options: AssembleOptions,
+}
+
+/// Words added by one `.org`, `.align` or `.space`, kept so they can be
+/// recomputed when instructions before them move.
+#[derive(Debug)]
+struct Padding {
+    start: usize,
+    len: usize,
+    directive: String,
+    operand: String,
+    span: SourceSpan,
+}
+
+impl ParsedAsm {
+    pub fn instrs_with_spans(&self) -> Vec<(&Instruction, SourceSpan)> {
+        self.instrs.iter().zip(self.spans.iter().copied()).collect()
+    }
+
+    /// Indices of the instructions execution may continue to after
+    /// `idx`. `br` targets are computed at runtime, so it has none.
+    pub fn successors(&self, idx: usize) -> Vec<usize> {
+        let instr = &self.instrs[idx];
+        let mut next = Vec::new();
+        if instr.falls_through() {
+            next.push(idx + 1);
+        }
+        if instr.has_abs_label() || instr.has_rel_label() {
+            if let Some(&target) = self.labels.get(instr.get_label_name()) {
+                let offset = instr.abs_label().map_or(0, AbsLabel::offset);
+                if let Some(target) = target.checked_add_signed(offset as isize / 4) {
+                    next.push(target);
+                }
+            }
+        }
+        next.retain(|&succ| succ < self.instrs.len());
+        next
+    }
+
+    /// Maximal straight-line runs `start..end` of the instruction stream.
+    /// Blocks begin at the entry, at label targets and after branches.
+    pub fn basic_blocks(&self) -> Vec<Range<usize>> {
+        let len = self.instrs.len();
+        let mut leaders = vec![false; len + 1];
+        leaders[0] = true;
+        leaders[len] = true;
+        for &target in self.labels.values() {
+            leaders[target.min(len)] = true;
+        }
+        for (idx, instr) in self.instrs.iter().enumerate() {
+            if !instr.falls_through() || instr.has_abs_label() || instr.has_rel_label() {
+                leaders[idx + 1] = true;
+            }
+        }
+        let starts: Vec<_> = (0..=len).filter(|&idx| leaders[idx]).collect();
+        starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+    }
+
+    /// Writes the basic-block control-flow graph as Graphviz DOT. Nodes
+    /// show their labels and address range; edges are marked as
+    /// fallthroughs or branches.
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let blocks = self.basic_blocks();
+        let block_of = |idx: usize| blocks.iter().position(|block| block.contains(&idx));
+        let addr = |idx: usize| {
+            self.options
+                .format_addr(self.options.base_addr as usize + 4 * idx)
+        };
+        writeln!(writer, "digraph cfg {{")?;
+        writeln!(writer, "    node [shape=box, fontname=\"monospace\"];")?;
+        for (block_idx, block) in blocks.iter().enumerate() {
+            let mut names: Vec<_> = self
+                .labels
+                .iter()
+                .filter(|(_, &idx)| idx == block.start)
+                .map(|(name, _)| format!("{}:\\n", name))
+                .collect();
+            names.sort();
+            writeln!(
+                writer,
+                "    b{} [label=\"{}{}-{}\"];",
+                block_idx,
+                names.concat(),
+                addr(block.start),
+                addr(block.end - 1)
+            )?;
+        }
+        for (block_idx, block) in blocks.iter().enumerate() {
+            let last = block.end - 1;
+            let instr = &self.instrs[last];
+            for (pos, succ) in self.successors(last).into_iter().enumerate() {
+                let kind = if pos == 0 && instr.falls_through() && succ == last + 1 {
+                    "fallthrough"
+                } else {
+                    "branch"
+                };
+                if let Some(succ_block) = block_of(succ) {
+                    writeln!(
+                        writer,
+                        "    b{} -> b{} [label=\"{}\"];",
+                        block_idx, succ_block, kind
+                    )?;
+                }
+            }
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    pub fn unreachable_instrs(&self) -> Vec<usize> {
+        let mut reached = vec![false; self.instrs.len()];
+        let mut pending = Vec::new();
+        if !self.instrs.is_empty() {
+            pending.push(0);
+        }
+        while let Some(idx) = pending.pop() {
+            if reached[idx] {
+                continue;
+            }
+            reached[idx] = true;
+            pending.extend(self.successors(idx));
+        }
+        // Data placed after code is never meant to be executed
+        (0..self.instrs.len())
+            .filter(|&idx| !reached[idx] && !self.instrs[idx].is_data())
+            .collect()
+    }
+
+    /// Read-after-write hazards where an instruction consumes a register
+    /// written fewer than `distance` + 1 instructions earlier, scanning
+    /// the stream in address order.
+    pub fn data_hazards(&self, distance: usize) -> Vec<Hazard> {
+        let mut hazards = Vec::new();
+        for (consumer, instr) in self.instrs.iter().enumerate() {
+            let window = consumer.saturating_sub(distance)..consumer;
+            for reg in instr.reads() {
+                let producer = window
+                    .clone()
+                    .rev()
+                    .find(|&idx| self.instrs[idx].writes() == Some(reg));
+                if let Some(producer) = producer {
+                    let hazard = Hazard {
+                        producer,
+                        consumer,
+                        reg,
+                    };
+                    if !hazards.contains(&hazard) {
+                        hazards.push(hazard);
+                    }
+                }
+            }
+        }
+        hazards
+    }
+
+    /// Runs `start..end` of two or more consecutive nops, as suggestions
+    /// for padding that could be reduced. A lone nop is usually a
+    /// deliberate delay and is not reported. The stream is left as is.
+    pub fn nop_runs(&self) -> Vec<Range<usize>> {
+        let mut runs = Vec::new();
+        let mut idx = 0;
+        while idx < self.instrs.len() {
+            let start = idx;
+            while idx < self.instrs.len() && self.instrs[idx] == Instruction::NOP {
+                idx += 1;
+            }
+            if idx - start > 1 {
+                runs.push(start..idx);
+            }
+            idx = idx.max(start + 1);
+        }
+        runs
+    }
+
+    /// Pads the stream with nops until no instruction reads a register
+    /// within `distance` instructions of its write, moving labels along
+    /// with their instructions. Layout padding is recomputed so `.org`
+    /// and `.align` keep their addresses. Returns the number of nops
+    /// inserted. Must run before `assign_labels`.
+    pub fn fix_hazards(&mut self, distance: usize) -> AssemblerResult<usize> {
+        self.fix_hazards_located(distance)
+            .map_err(|diag| diag.error)
+    }
+
+    fn fix_hazards_located(&mut self, distance: usize) -> Result<usize, Diagnostic> {
+        let old_len = self.instrs.len();
+        let old_spans = std::mem::take(&mut self.spans);
+        let mut old = std::mem::take(&mut self.instrs).into_iter().zip(old_spans);
+        let mut old_padding = std::mem::take(&mut self.padding).into_iter().peekable();
+        // new_idx[i] is where the i-th old instruction (or its padding) starts
+        let mut new_idx = Vec::with_capacity(old_len + 1);
+        let mut inserted = 0;
+        let mut old_idx = 0;
+        loop {
+            while let Some(mut padding) = old_padding.next_if(|padding| padding.start == old_idx) {
+                let start = self.instrs.len();
+                let located = |error| Diagnostic {
+                    span: padding.span,
+                    error,
+                };
+                let len = padding_len(
+                    &padding.directive,
+                    &padding.operand,
+                    self.options.base_addr,
+                    start,
+                    &self.options,
+                )
+                .map_err(located)?;
+                push_padding(&mut self.instrs, &mut self.spans, len, padding.span)
+                    .map_err(located)?;
+                for word in 0..padding.len {
+                    old.next();
+                    new_idx.push((start + word).min(self.instrs.len()));
+                }
+                old_idx += padding.len;
+                padding.start = start;
+                padding.len = len;
+                self.padding.push(padding);
+            }
+            let (instr, span) = match old.next() {
+                Some(next) => next,
+                None => break,
+            };
+            old_idx += 1;
+            let consumer = self.instrs.len();
+            let window = consumer.saturating_sub(distance)..consumer;
+            let nops = instr
+                .reads()
+                .into_iter()
+                .filter_map(|reg| {
+                    window
+                        .clone()
+                        .rev()
+                        .find(|&idx| self.instrs[idx].writes() == Some(reg))
+                })
+                .map(|producer| distance + 1 - (consumer - producer))
+                .max()
+                .unwrap_or(0);
+            new_idx.push(consumer);
+            for _ in 0..nops {
+                // adds $zero to itself, so it encodes as the all-zero word
+                self.instrs.push(Instruction::NOP);
+                self.spans.push(span);
+            }
+            inserted += nops;
+            self.instrs.push(instr);
+            self.spans.push(span);
+        }
+        new_idx.push(self.instrs.len());
+        for label_idx in self.labels.values_mut() {
+            *label_idx = new_idx[*label_idx];
+        }
+        // Comments stay with their instruction rather than its padding
+        self.comments = std::mem::take(&mut self.comments)
+            .into_iter()
+            .map(|(idx, text)| match new_idx.get(idx + 1) {
+                Some(next) => (next - 1, text),
+                None => (new_idx[idx], text),
+            })
+            .collect();
+        Ok(inserted)
+    }
+
+    /// Occurrences of each mnemonic after expansion, most used first.
+    pub fn mnemonic_stats(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: HashMap<&'static str, usize> = HashMap::new();
+        for instr in &self.instrs {
+            *counts.entry(instr.opname()).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|(op, count), (other_op, other_count)| {
+            other_count.cmp(count).then(op.cmp(other_op))
+        });
+        counts
+    }
+
+    /// Read and write counts for every register the program touches,
+    /// most used first.
+    pub fn register_stats(&self) -> Vec<RegisterUsage> {
+        let mut usage: Vec<_> = (0..32)
+            .map(|reg| RegisterUsage {
+                reg,
+                reads: 0,
+                writes: 0,
+            })
+            .collect();
+        for instr in &self.instrs {
+            for reg in instr.reads() {
+                usage[reg as usize].reads += 1;
+            }
+            if let Some(reg) = instr.writes() {
+                usage[reg as usize].writes += 1;
+            }
+        }
+        usage.retain(|entry| entry.reads + entry.writes > 0);
+        usage.sort_by_key(|entry| (std::cmp::Reverse(entry.reads + entry.writes), entry.reg));
+        usage
+    }
+
+    pub fn write_stats<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        writeln!(writer, "{:<10} {:>6}", "mnemonic", "count")?;
+        for (op, count) in self.mnemonic_stats() {
+            writeln!(writer, "{:<10} {:>6}", op, count)?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "{:<10} {:>6} {:>6}", "register", "reads", "writes")?;
+        for entry in self.register_stats() {
+            writeln!(
+                writer,
+                "{:<10} {:>6} {:>6}",
+                instruction::register_name(entry.reg),
+                entry.reads,
+                entry.writes
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Labels spelled like a mnemonic, register or directive, paired with
+    /// what they shadow.
+    pub fn shadowing_labels(&self) -> Vec<(&str, &'static str)> {
+        let mut shadowing: Vec<_> = self
+            .labels
+            .keys()
+            .filter_map(|label| {
+                let bare = label.strip_prefix('.').unwrap_or(label);
+                let kind = if instruction::is_mnemonic(bare) {
+                    "mnemonic"
+                } else if instruction::is_register_name(bare) {
+                    "register"
+                } else if DIRECTIVES.contains(&bare) {
+                    "directive"
+                } else {
+                    return None;
+                };
+                Some((label.as_str(), kind))
+            })
+            .collect();
+        shadowing.sort();
+        shadowing
+    }
+
+    pub fn check_labels(&self) -> Vec<AssemblerError> {
+        errors_of(self.label_diagnostics())
+    }
+
+    /// Same checks as `check_labels`, located at the referencing instruction.
+    pub fn label_diagnostics(&self) -> Vec<Diagnostic> {
+        let undefined = |name: &str| AssemblerError::UndefinedLabel(String::from(name));
+        let mut diags: Vec<_> = self
+            .instrs
+            .iter()
+            .zip(&self.spans)
+            .filter(|(instr, _)| instr.has_label())
+            .filter(|(instr, _)| !self.labels.contains_key(instr.get_label_name()))
+            .map(|(instr, &span)| Diagnostic {
+                span,
+                error: undefined(instr.get_label_name()),
+            })
+            .collect();
+        if let Some(entry) = self.entry.as_deref() {
+            if !self.labels.contains_key(entry) {
+                diags.push(Diagnostic::from(undefined(entry)));
+            }
+        }
+        if self.options.require_entry && self.entry.is_none() {
+            diags.push(Diagnostic::from(AssemblerError::MissingEntry));
+        }
+        diags
+    }
+
+    /// Labels shadowing another name, as errors under `strict` and as
+    /// warnings (recorded in `warnings`) otherwise.
+    fn shadowing_diagnostics(&mut self) -> Vec<Diagnostic> {
+        let mut diags = Vec::new();
+        let mut warnings = Vec::new();
+        for (label, kind) in self.shadowing_labels() {
+            let span = self.label_spans.get(label).copied();
+            let span = span.unwrap_or_default();
+            if self.options.strict {
+                diags.push(Diagnostic {
+                    span,
+                    error: AssemblerError::ShadowingLabel {
+                        label: String::from(label),
+                        kind,
+                    },
+                });
+            } else {
+                warnings.push(Warning {
+                    span,
+                    message: format!("label `{}` shadows a {} name", label, kind),
+                });
+            }
+        }
+        self.warnings.extend(warnings);
+        diags
+    }
+
+    /// Runs `fix_hazards` when the options ask for it, noting any
+    /// inserted nops as a warning.
+    fn apply_hazard_fixes(&mut self) -> Result<(), Diagnostic> {
+        if !self.options.fix_hazards {
+            return Ok(());
+        }
+        let inserted = self.fix_hazards_located(self.options.hazard_distance)?;
+        if inserted > 0 {
+            self.warnings.push(Warning {
+                span: SourceSpan::default(),
+                message: format!("inserted {} nop(s) to resolve hazards", inserted),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn assign_labels(&mut self, off: u32) -> AssemblerResult<()> {
+        self.assign_labels_located(off).map_err(|diag| diag.error)
+    }
+
+    fn assign_labels_located(&mut self, off: u32) -> Result<(), Diagnostic> {
+        // Every word and label needs a 32-bit address
+        if let Some(last) = self.instrs.len().checked_sub(1) {
+            word_addr(off, last).map_err(|error| Diagnostic {
+                span: self.spans[last],
+                error,
+            })?;
+        }
+        for (name, &idx) in &self.labels {
+            word_addr(off, idx).map_err(|error| Diagnostic {
+                span: self.label_spans.get(name).copied().unwrap_or_default(),
+                error,
+            })?;
+        }
+        let labels = &self.labels;
+        let label_idx = |name: &str| {
+            labels
+                .get(name)
+                .copied()
+                .ok_or_else(|| AssemblerError::UndefinedLabel(String::from(name)))
+        };
+        for (idx, (instr, &span)) in self.instrs.iter_mut().zip(&self.spans).enumerate() {
+            let located = |error| Diagnostic { span, error };
+            if instr.abs_label().is_some() {
+                // Since our memory is small, we can
+                // directly store the PC value in an AbsLabel
+                let name = instr.get_label_name();
+                let label_addr =
+                    word_addr(off, label_idx(name).map_err(located)?).map_err(located)?;
+                let offset = instr.abs_label().map_or(0, AbsLabel::offset);
+                let addr = label_addr.checked_add_signed(offset).ok_or_else(|| {
+                    located(AssemblerError::ImmediateOutOfRange {
+                        value: format!("{} {:+}", name, offset),
+                        bits: 32,
+                    })
+                })?;
+                if instr.has_abs_label() && addr >> instruction::JTYPE_ADDR_BITS != 0 {
+                    return Err(located(AssemblerError::ImmediateOutOfRange {
+                        value: instr
+                            .abs_label()
+                            .map_or_else(String::new, AbsLabel::to_string),
+                        bits: instruction::JTYPE_ADDR_BITS,
+                    }));
+                }
+                instr.set_abs_addr(addr);
+            } else if instr.has_rel_label() {
+                // It is relative to PC + 4
+                let name = instr.get_label_name();
+                let label_idx = label_idx(name).map_err(located)?;
+                // Counted in words, and can be negative
+                let offset = label_idx as i64 - (idx as i64 + 1);
+                let imm = i16::try_from(offset).map_err(|_| {
+                    located(AssemblerError::BranchOutOfRange {
+                        label: String::from(name),
+                        offset,
+                    })
+                })?;
+                // But eventually, we want unsigned!
+                instr.set_rel_addr(imm as u16);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn image(&self) -> AssemblerResult<Vec<Word>> {
+        let mut words = self
+            .instrs
+            .iter()
+            .map(|instr| self.encode(instr))
+            .collect::<AssemblerResult<Vec<_>>>()?;
+        if let Some(depth) = self.options.depth {
+            if words.len() > depth {
+                return Err(AssemblerError::ImageTooLarge {
+                    needed: words.len(),
+                    depth,
+                });
+            }
+            // Zero words, so the padding decodes as nops with the built-in table
+            words.resize(depth, Word::new(0, self.options.word_width));
+        }
+        if let Some(size) = self.options.image_size {
+            let word_bytes = self.options.word_bytes();
+            if size % word_bytes != 0 {
+                return Err(AssemblerError::InvalidImageSize(size));
+            }
+            let needed = words.len() * word_bytes;
+            if needed > size {
+                return Err(AssemblerError::ImageSizeExceeded { needed, size });
+            }
+            words.resize(size / word_bytes, Word::new(0, self.options.word_width));
+        }
+        if self.options.reverse {
+            words.reverse();
+        }
+        Ok(words)
+    }
+
+    fn encode(&self, instr: &Instruction) -> AssemblerResult<Word> {
+        match &self.options.isa {
+            Some(table) => {
+                // Tables are checked to cover every mnemonic when loaded
+                let opcode = table.opcode(instr.opname()).unwrap_or_default();
+                instr.encode_word_with(opcode, self.options.word_width)
+            }
+            None => instr.encode_word(self.options.word_width),
+        }
+    }
+
+    /// Address of the first word where the two images differ, counted
+    /// from this program's base address as listings and maps show it,
+    /// or `None` if they encode identically.
+    pub fn first_difference(&self, other: &ParsedAsm) -> AssemblerResult<Option<u32>> {
+        let words = self.image()?;
+        let other_words = other.image()?;
+        let mismatch = words
+            .iter()
+            .zip(other_words.iter())
+            .position(|(word, other_word)| word != other_word);
+        let diff_idx = match mismatch {
+            Some(idx) => Some(idx),
+            None if words.len() != other_words.len() => Some(words.len().min(other_words.len())),
+            None => None,
+        };
+        diff_idx
+            .map(|idx| {
+                let addr = self.options.base_addr as u64 + (self.options.word_bytes() * idx) as u64;
+                u32::try_from(addr).map_err(|_| AssemblerError::AddressOutOfRange(addr))
+            })
+            .transpose()
+    }
+
+    /// Writes a symbol map, one `address  label  words  crc` line per label
+    /// sorted by address, using the addresses `assign_labels` gives them.
+    /// Each label starts a segment running to the next label at a higher
+    /// address or the end of the code; the CRC-32 covers its big-endian
+    /// bytes, as in `image_bytes`, so it can be checked after flashing.
+    pub fn write_map<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, &idx)| (idx, *name));
+        for (pos, &(name, &idx)) in labels.iter().enumerate() {
+            let start = idx.min(self.instrs.len());
+            let end = labels[pos..]
+                .iter()
+                .map(|(_, &next)| next)
+                .find(|&next| next > idx)
+                .unwrap_or(self.instrs.len())
+                .min(self.instrs.len());
+            let mut bytes = Vec::with_capacity(4 * (end - start));
+            for instr in &self.instrs[start..end] {
+                bytes.extend(self.encode(instr)?.to_be_bytes());
+            }
+            let addr = self.options.base_addr as usize + 4 * idx;
+            writeln!(
+                writer,
+                "{}  {}  {}  {:08x}",
+                self.options.format_addr(addr),
+                name,
+                end - start,
+                crc::crc32(&bytes)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes a listing: every source line with the address it lands at
+    /// and, for lines that emit words, their encoding. Lines expanding to
+    /// several words list the extra ones on lines of their own. Relative
+    /// branches are annotated with their target address and word offset.
+    pub fn write_listing<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let blank = " ".repeat(Word::new(0, self.options.word_width).to_hex().len());
+        let addr_of = |idx: usize| {
+            self.options
+                .format_addr(self.options.base_addr as usize + 4 * idx)
+        };
+        let mut idx = 0;
+        for (line_idx, text) in self.source.iter().enumerate() {
+            let mut encodings = Vec::new();
+            while idx < self.instrs.len() && self.spans[idx].line <= line_idx + 1 {
+                let instr = &self.instrs[idx];
+                // Relative branches are offsets from the next instruction
+                let target = instr.rel_label().and_then(RelLabel::addr).map(|offset| {
+                    let offset = offset as i16 as isize;
+                    let target = (idx as isize + 1 + offset) as usize;
+                    format!("  # -> {} ({:+})", addr_of(target), offset)
+                });
+                encodings.push((
+                    addr_of(idx),
+                    self.encode(instr)?.to_hex(),
+                    target.unwrap_or_default(),
+                ));
+                idx += 1;
+            }
+            let mut encodings = encodings.into_iter();
+            let line = match encodings.next() {
+                Some((addr, hex, target)) => format!("{}  {}  {}{}", addr, hex, text, target),
+                None => format!("{}  {}  {}", addr_of(idx), blank, text),
+            };
+            writeln!(writer, "{}", line.trim_end())?;
+            for (addr, hex, target) in encodings {
+                writeln!(writer, "{}  {}{}", addr, hex, target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the fully-lowered program back out as assembly,
+    /// one real instruction per line.
+    pub fn write_expanded<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, &idx)| (idx, *name));
+        let mut labels = labels.into_iter().peekable();
+        for idx in 0..=self.instrs.len() {
+            while let Some((name, _)) = labels.next_if(|(_, &label_idx)| label_idx == idx) {
+                writeln!(writer, "{}:", name)?;
+            }
+            if let Some(instr) = self.instrs.get(idx) {
+                match self.comments.get(&idx) {
+                    Some(text) => writeln!(writer, "\t{}\t// {}", instr, text)?,
+                    None => writeln!(writer, "\t{}", instr)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, format: OutputFormat, path: P) -> AssemblerResult<()> {
+        let array_name = c_identifier(path.as_ref());
+        let mut file = File::create(path)?;
+        self.write_to(format, &mut file, &array_name)
+    }
+
+    /// Like `write`, but to any writer. `array_name` names the array of
+    /// a C header, which `write` takes from the file name.
+    pub fn write_to<W: Write>(
+        &self,
+        format: OutputFormat,
+        writer: &mut W,
+        array_name: &str,
+    ) -> AssemblerResult<()> {
+        match format {
+            OutputFormat::Coe => self.write_coe_to(writer),
+            OutputFormat::Mif => self.write_mif_to(writer),
+            OutputFormat::ReadMem(MemRadix::Hex) => self.write_readmemh(writer),
+            OutputFormat::ReadMem(MemRadix::Binary) => self.write_readmemb(writer),
+            OutputFormat::CHeader => self.write_c_header(writer, array_name),
+            OutputFormat::Python(literal) => self.write_python(writer, literal),
+            OutputFormat::Bin(endian) => self.write_bin(writer, endian),
+        }
+    }
+
+    pub fn image_bytes(&self) -> AssemblerResult<Vec<u8>> {
+        Ok(self
+            .image()?
+            .into_iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect())
+    }
+
+    /// The image as raw bytes, each word in the given byte order, after
+    /// the provenance header if `bin_header` asks for one.
+    pub fn bin_bytes(&self, endian: Endianness) -> AssemblerResult<Vec<u8>> {
+        let words = self.image()?;
+        let mut bytes = Vec::new();
+        if let Some(name) = &self.options.bin_header {
+            bytes.extend(BIN_HEADER_MAGIC);
+            bytes.extend(BIN_HEADER_VERSION.to_be_bytes());
+            bytes.extend((words.len() as u32).to_be_bytes());
+            // Cut on a character boundary so the name stays valid UTF-8
+            let mut len = name.len().min(BIN_HEADER_NAME_BYTES);
+            while !name.is_char_boundary(len) {
+                len -= 1;
+            }
+            let mut field = [0; BIN_HEADER_NAME_BYTES];
+            field[..len].copy_from_slice(&name.as_bytes()[..len]);
+            bytes.extend(field);
+        }
+        for word in words {
+            match endian {
+                Endianness::Little => bytes.extend(word.to_le_bytes()),
+                Endianness::Big => bytes.extend(word.to_be_bytes()),
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Writes `bin_bytes` in the given byte order.
+    pub fn write_bin<W: Write>(&self, writer: &mut W, endian: Endianness) -> AssemblerResult<()> {
+        writer.write_all(&self.bin_bytes(endian)?)?;
+        Ok(())
+    }
+
+    pub fn crc32(&self) -> AssemblerResult<u32> {
+        Ok(crc::crc32(&self.image_bytes()?))
+    }
+
+    pub fn verify_crc(&self) -> AssemblerResult<()> {
+        let expected = match self.expected_crc {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let actual = self.crc32()?;
+        if actual != expected {
+            return Err(AssemblerError::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    pub fn write_python<W: Write>(
+        &self,
+        writer: &mut W,
+        literal: PythonLiteral,
+    ) -> AssemblerResult<()> {
+        let words = self.image()?;
+        match literal {
+            PythonLiteral::Bytes => {
+                write!(writer, "program = bytes.fromhex(\"")?;
+                for word in &words {
+                    write!(writer, "{}", word.to_hex())?;
+                }
+                writeln!(writer, "\")")?;
+            }
+            PythonLiteral::List => {
+                writeln!(writer, "program = [")?;
+                for word in &words {
+                    writeln!(writer, "    0x{},", word.to_hex())?;
+                }
+                writeln!(writer, "]")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One zero-padded hex word per line, as read by `$readmemh`.
+    pub fn write_readmemh<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        for word in self.image()? {
+            writeln!(writer, "{}", word.to_hex())?;
+        }
+        Ok(())
+    }
+
+    /// One binary word per line, as read by `$readmemb`.
+    pub fn write_readmemb<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        for word in self.image()? {
+            writeln!(
+                writer,
+                "{:0width$b}",
+                word.value(),
+                width = word.width() as usize
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A C array of the image words with a `<NAME>_LEN` length macro,
+    /// wrapped in an include guard derived from `array_name`.
+    pub fn write_c_header<W: Write>(
+        &self,
+        writer: &mut W,
+        array_name: &str,
+    ) -> AssemblerResult<()> {
+        let words = self.image()?;
+        let macro_name = array_name.to_uppercase();
+        let c_type = if self.options.word_width <= 32 {
+            "uint32_t"
+        } else {
+            "uint64_t"
+        };
+        writeln!(writer, "#ifndef {}_H", macro_name)?;
+        writeln!(writer, "#define {}_H", macro_name)?;
+        writeln!(writer)?;
+        writeln!(writer, "#include <stdint.h>")?;
+        writeln!(writer)?;
+        writeln!(writer, "const {} {}[] = {{", c_type, array_name)?;
+        for row in words.chunks(8) {
+            let row: Vec<_> = row
+                .iter()
+                .map(|word| format!("0x{}", word.to_hex()))
+                .collect();
+            writeln!(writer, "    {},", row.join(", "))?;
+        }
+        writeln!(writer, "}};")?;
+        writeln!(writer)?;
+        writeln!(writer, "#define {}_LEN {}", macro_name, words.len())?;
+        writeln!(writer)?;
+        writeln!(writer, "#endif /* {}_H */", macro_name)?;
+        Ok(())
+    }
+
+    pub fn write_mif<P: AsRef<Path>>(&self, path: P) -> AssemblerResult<()> {
+        let mut file = File::create(path)?;
+        self.write_mif_to(&mut file)
+    }
+
+    /// Quartus-style MIF with hex addresses and data. The depth is the
+    /// configured one, or else the image rounded up to a power of two.
+    /// Runs of a repeated word, such as the zeroed tail, become one range.
+    pub fn write_mif_to<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let words = self.image()?;
+        let depth = match self.options.depth {
+            Some(depth) => depth.max(words.len()),
+            None => words.len().max(1).next_power_of_two(),
+        };
+        writeln!(writer, "DEPTH = {};", depth)?;
+        writeln!(writer, "WIDTH = {};", self.options.word_width)?;
+        writeln!(writer, "ADDRESS_RADIX = HEX;")?;
+        writeln!(writer, "DATA_RADIX = HEX;")?;
+        writeln!(writer, "CONTENT BEGIN")?;
+        let zero = Word::new(0, self.options.word_width);
+        // Runs of one repeated word, including the zero fill, share a range
+        let mut start = 0;
+        while start < depth {
+            let word = words.get(start).copied().unwrap_or(zero);
+            let mut end = start + 1;
+            while end < depth && words.get(end).copied().unwrap_or(zero) == word {
+                end += 1;
+            }
+            if end - start == 1 {
+                writeln!(writer, "    {:x} : {};", start, word.to_hex())?;
+            } else {
+                writeln!(
+                    writer,
+                    "    [{:x}..{:x}] : {};",
+                    start,
+                    end - 1,
+                    word.to_hex()
+                )?;
+            }
+            start = end;
+        }
+        writeln!(writer, "END;")?;
+        Ok(())
+    }
+
+    pub fn write_coe<P: AsRef<Path>>(&self, path: P) -> AssemblerResult<()> {
+        let mut file = File::create(path)?;
+        self.write_coe_to(&mut file)
+    }
+
+    pub fn write_coe_to<W: Write>(&self, writer: &mut W) -> AssemblerResult<()> {
+        let words = self.image()?;
+        let radix = self.options.coe_radix;
+        writeln!(writer, "{}={};", coe::RADIX_KEY, radix.radix())?;
+        if words.is_empty() {
+            // A lone `;` line would read as a comment
+            writeln!(writer, "{}=;", coe::VECTOR_KEY)?;
+        } else {
+            writeln!(writer, "{}=", coe::VECTOR_KEY)?;
+        }
+        for (idx, word) in words.iter().enumerate() {
+            if let Some(text) = self.comments.get(&idx) {
+                writeln!(writer, "; {}", text)?;
+            }
+            match radix {
+                CoeRadix::Binary => write!(
+                    writer,
+                    "{:0width$b}",
+                    word.value(),
+                    width = word.width() as usize
+                )?,
+                CoeRadix::Decimal { signed: false } => write!(writer, "{}", word.value())?,
+                CoeRadix::Decimal { signed: true } => write!(writer, "{}", word.signed_value())?,
+                CoeRadix::Hex => write!(writer, "{}", word.to_hex())?,
+            }
+            if idx == words.len() - 1 {
+                writeln!(writer, ";")?;
+            } else {
+                writeln!(writer, ",")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Knobs controlling how source is assembled, defaulting to the
+/// behaviour of the plain two-argument CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleOptions {
+    // Address of the first instruction
+    pub base_addr: u32,
+    // Bits per instruction-memory word
+    pub word_width: u32,
+    // Exact size of the output image in bytes, padded with zero words
+    pub image_size: Option<usize>,
+    pub coe_radix: CoeRadix,
+    // Decompress the input even without a `.gz` extension
+    pub gzip: bool,
+    // Reject operands not written as `mnemonic op1, op2`
+    pub strict_format: bool,
+    // Number of words in the target instruction memory
+    pub depth: Option<usize>,
+    // Reject pseudo-instructions instead of expanding them
+    pub no_pseudo: bool,
+    // Hex digits used when printing addresses, 8 when unset
+    pub addr_digits: Option<usize>,
+    // Instructions a result takes to become visible to later ones
+    pub hazard_distance: usize,
+    // Insert nops so no read falls within `hazard_distance` of its write
+    pub fix_hazards: bool,
+    // Make warnings about confusing label names errors
+    pub strict: bool,
+    // Fail unless the program declares `.entry` or `.global`
+    pub require_entry: bool,
+    // Opcodes loaded with `--isa` in place of the built-in table
+    pub isa: Option<OpcodeTable>,
+    // Store the image highest address first, padding included; labels
+    // still resolve to their normal addresses
+    pub reverse: bool,
+    // Stop at the first bad line instead of reporting every one
+    pub fail_fast: bool,
+    // Source name for a provenance header before raw binary images
+    pub bin_header: Option<String>,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            base_addr: 0,
+            word_width: DEFAULT_WORD_WIDTH,
+            image_size: None,
+            coe_radix: CoeRadix::Binary,
+            gzip: false,
+            strict_format: false,
+            depth: None,
+            no_pseudo: false,
+            addr_digits: None,
+            hazard_distance: 1,
+            fix_hazards: false,
+            strict: false,
+            require_entry: false,
+            isa: None,
+            reverse: false,
+            fail_fast: false,
+            bin_header: None,
+        }
+    }
+}
+
+impl AssembleOptions {
+    /// Bytes each word takes in a raw image, rounding partial bytes up.
+    pub fn word_bytes(&self) -> usize {
+        (self.word_width as usize).div_ceil(8)
+    }
+
+    pub fn format_addr(&self, addr: usize) -> String {
+        format!(
+            "0x{:0digits$x}",
+            addr,
+            digits = self.addr_digits.unwrap_or(8)
+        )
+    }
+}
+
+/// Parses a source file with the default options, leaving labels
+/// unresolved until `ParsedAsm::assign_labels` is called.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> AssemblerResult<ParsedAsm> {
+    let file = File::open(path)?;
+    parse_reader(BufReader::new(file), &AssembleOptions::default())
+}
+
+pub fn parse_reader<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+) -> AssemblerResult<ParsedAsm> {
+    parse_reader_located(reader, options).map_err(|diag| diag.error)
+}
+
+fn parse_reader_located<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+) -> Result<ParsedAsm, Diagnostic> {
+    let (parsed_asm, diagnostics) = parse_source(reader, options, true);
+    match diagnostics.into_iter().next() {
+        Some(diag) => Err(diag),
+        None => Ok(parsed_asm),
+    }
+}
+
+/// An error tied to the source line that caused it.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub span: SourceSpan,
+    pub error: AssemblerError,
+}
+
+impl From<AssemblerError> for Diagnostic {
+    fn from(error: AssemblerError) -> Self {
+        Diagnostic {
+            span: SourceSpan::default(),
+            error,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Formats the error under the offending source line, rustc-style:
+    ///
+    /// ```text
+    ///   42 | addx $t0, $t1
+    ///      | ^^^^ unknown instruction `addx`
+    /// ```
+    ///
+    /// Errors without a line are rendered as just the message.
+    pub fn render(&self, source: &str) -> String {
+        let text = match self.span.line.checked_sub(1) {
+            Some(idx) => source.lines().nth(idx),
+            None => None,
+        };
+        let text = match text {
+            Some(text) => text,
+            None => return self.error.to_string(),
+        };
+        let (start, end) = self.underline(text);
+        let width = self.span.line.to_string().len() + 2;
+        // Keep tabs so the carets line up however the terminal expands them
+        let pad: String = text[..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{:>width$} | {}\n{:width$} | {}{} {}",
+            self.span.line,
+            text,
+            "",
+            pad,
+            "^".repeat((end - start).max(1)),
+            self.error,
+        )
+    }
+
+    /// Byte range to underline: the token the error names if it occurs
+    /// within the span, otherwise the whole span.
+    fn underline(&self, text: &str) -> (usize, usize) {
+        let end = self.span.end.min(text.len());
+        let start = self.span.start.min(end);
+        let token = match &self.error {
+            AssemblerError::UnknownInstruction(token)
+            | AssemblerError::UnknownRegister(token)
+            | AssemblerError::InvalidNumber(token)
+            | AssemblerError::InvalidExpression(token)
+            | AssemblerError::PseudoInstruction(token)
+            | AssemblerError::UndefinedLabel(token)
+            | AssemblerError::DuplicateLabel(token)
+            | AssemblerError::DuplicateConstant(token)
+            | AssemblerError::UnalignedOffset(token)
+            | AssemblerError::ImmediateOutOfRange { value: token, .. } => token.as_str(),
+            _ => "",
+        };
+        match text.get(start..end).and_then(|span| span.find(token)) {
+            Some(offset) if !token.is_empty() => (start + offset, start + offset + token.len()),
+            _ => (start, end),
+        }
+    }
+}
+
+/// Like `parse_reader`, but keeps going past lines that fail to parse.
+/// Each failed line becomes a single nop placeholder, so labels after it
+/// still index the instructions they would have without the error.
+/// Blank lines are skipped rather than reported.
+pub fn parse_lenient<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+) -> (ParsedAsm, Vec<Diagnostic>) {
+    parse_source(reader, options, false)
+}
+
+/// The line loop behind both parsers. With `stop_at_first_error` it
+/// returns as soon as one diagnostic is recorded.
+fn parse_source<R: BufRead>(
+    reader: R,
+    options: &AssembleOptions,
+    stop_at_first_error: bool,
+) -> (ParsedAsm, Vec<Diagnostic>) {
+    let mut instrs = Vec::new();
+    let mut spans = Vec::new();
+    let mut labels = HashMap::new();
+    let mut entry = None;
+    let mut comments = HashMap::new();
+    let mut expected_crc = None;
+    let mut origin = options.base_addr;
+    // Whether a `.org` before any code has already placed the origin
+    let mut origin_set = false;
+    let mut diagnostics = Vec::new();
+    let mut label_spans = HashMap::new();
+    let mut warnings = Vec::new();
+    let mut padding = Vec::new();
+    let (lines, mut read_error) = read_lines(reader);
+    if stop_at_first_error {
+        diagnostics.extend(read_error.take());
+    }
+    let constants = collect_constants(&lines, &mut diagnostics, &mut warnings);
+    for (line_idx, raw_line) in lines.iter().enumerate() {
+        if stop_at_first_error && !diagnostics.is_empty() {
+            break;
+        }
+        if let Some(crc) = detect_expected_crc(raw_line) {
+            expected_crc = Some(crc);
+            continue;
+        }
+        if let Some(text) = detect_comment(raw_line) {
+            add_comment(&mut comments, instrs.len(), text);
+            continue;
+        }
+        let line = match strip_comment(raw_line) {
+            Some(line) if !line.trim().is_empty() => line,
+            _ => continue,
+        };
+        if let Some(label) = detect_entry(line) {
+            entry = Some(String::from(label));
+            continue;
+        }
+        // Constants were collected by the first pass
+        if detect_equ(line).is_some() {
+            continue;
+        }
+        if let Some((directive, operand)) = detect_layout(line) {
+            let span = line_span(line_idx, raw_line, line);
+            if let Err(error) = apply_layout(
+                directive,
+                operand,
+                &mut origin,
+                &mut origin_set,
+                &mut instrs,
+                &mut spans,
+                &mut padding,
+                span,
+                options,
+            ) {
+                diagnostics.push(Diagnostic { span, error });
+            }
+            continue;
+        }
+        if let Some((label, count)) = detect_range(line) {
+            let span = line_span(line_idx, raw_line, line);
+            let defined = define_label(&mut labels, &mut label_spans, label, instrs.len(), span);
+            let reserved = defined.and_then(|_| {
+                apply_layout(
+                    "space",
+                    count,
+                    &mut origin,
+                    &mut origin_set,
+                    &mut instrs,
+                    &mut spans,
+                    &mut padding,
+                    span,
+                    options,
+                )
+            });
+            if let Err(error) = reserved {
+                diagnostics.push(Diagnostic { span, error });
+            }
+            continue;
+        }
+        let line = match detect_label(line) {
+            Some((label, rest)) => {
+                let span = line_span(line_idx, raw_line, line);
+                if let Err(error) =
+                    define_label(&mut labels, &mut label_spans, label, instrs.len(), span)
+                {
+                    diagnostics.push(Diagnostic { span, error });
+                }
+                if rest.is_empty() {
+                    continue;
+                }
+                rest
+            }
+            None => line,
+        };
+        let span = line_span(line_idx, raw_line, line);
+        let expanded = parse_line(line, options, &constants).unwrap_or_else(|error| {
+            diagnostics.push(Diagnostic { span, error });
+            vec![Instruction::NOP]
+        });
+        spans.extend(expanded.iter().map(|_| span));
+        instrs.extend(expanded);
+    }
+    diagnostics.extend(read_error);
+    let parsed_asm = ParsedAsm {
+        instrs,
+        spans,
+        labels,
+        label_spans,
+        entry,
+        comments,
+        expected_crc,
+        constants,
+        source: lines,
+        warnings,
+        padding,
+        options: AssembleOptions {
+            base_addr: origin,
+            ..options.clone()
+        },
+    };
+    (parsed_asm, diagnostics)
+}
+
+/// Assembles as much of a program as possible, for editor integrations
+/// that need labels and spans even while the source has errors.
+/// Instructions naming undefined labels are replaced by placeholders too.
+pub fn assemble_lenient(source: &str, options: &AssembleOptions) -> (ParsedAsm, Vec<Diagnostic>) {
+    let (mut parsed_asm, mut diagnostics) = parse_lenient(source.as_bytes(), options);
+    diagnostics.extend(parsed_asm.label_diagnostics());
+    for idx in 0..parsed_asm.instrs.len() {
+        let instr = &parsed_asm.instrs[idx];
+        if instr.has_label() && !parsed_asm.labels.contains_key(instr.get_label_name()) {
+            parsed_asm.instrs[idx] = Instruction::NOP;
+        }
+    }
+    diagnostics.extend(parsed_asm.shadowing_diagnostics());
+    if let Err(diag) = parsed_asm.apply_hazard_fixes() {
+        diagnostics.push(diag);
+    }
+    // Every remaining label is defined, so this only fails on bad addresses
+    if let Err(diag) = parsed_asm.assign_labels_located(parsed_asm.options.base_addr) {
+        diagnostics.push(diag);
+    }
+    // Placeholders would make any checksum mismatch
+    if diagnostics.is_empty() {
+        if let Err(error) = parsed_asm.verify_crc() {
+            diagnostics.push(Diagnostic::from(error));
+        }
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.line);
+    (parsed_asm, diagnostics)
+}
+
+/// A non-fatal finding about the program.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub span: SourceSpan,
+    pub message: String,
+}
+
+/// Everything a front-end needs from one assembly run.
+#[derive(Debug)]
+pub struct AssembleReport {
+    // Empty unless assembly succeeded
+    pub words: Vec<u32>,
+    // Label names and byte addresses, in address order
+    pub symbols: Vec<(String, u32)>,
+    pub warnings: Vec<Warning>,
+    pub errors: Vec<Diagnostic>,
+    pub mnemonic_stats: Vec<(&'static str, usize)>,
+    pub register_stats: Vec<RegisterUsage>,
+}
+
+/// Assembles leniently and gathers the image, symbols, diagnostics and
+/// usage statistics in one place, for editors and other front-ends.
+pub fn assemble_report(source: &str, options: &AssembleOptions) -> AssembleReport {
+    let (mut parsed_asm, mut errors) = assemble_lenient(source, options);
+    let mut warnings = std::mem::take(&mut parsed_asm.warnings);
+    for idx in parsed_asm.unreachable_instrs() {
+        warnings.push(Warning {
+            span: parsed_asm.spans[idx],
+            message: String::from("unreachable instruction"),
+        });
+    }
+    let mut words = Vec::new();
+    if errors.is_empty() {
+        match parsed_asm.image() {
+            Ok(image) => words = image.iter().map(|word| word.value() as u32).collect(),
+            Err(error) => errors.push(Diagnostic {
+                span: SourceSpan::default(),
+                error,
+            }),
+        }
+    }
+    let mut symbols: Vec<_> = parsed_asm
+        .labels
+        .iter()
+        .map(|(name, &idx)| {
+            // Checked by `assign_labels`, unless that already failed
+            let addr = word_addr(parsed_asm.options.base_addr, idx).unwrap_or_default();
+            (name.clone(), addr)
+        })
+        .collect();
+    symbols.sort_by(|(name, addr), (other_name, other_addr)| {
+        addr.cmp(other_addr).then(name.cmp(other_name))
+    });
+    AssembleReport {
+        words,
+        symbols,
+        warnings,
+        errors,
+        mnemonic_stats: parsed_asm.mnemonic_stats(),
+        register_stats: parsed_asm.register_stats(),
+    }
+}
+
+/// Markers that start a comment running to the end of the line. No
+/// operand syntax in this ISA uses `#` or `;` (immediates are bare
+/// numbers), so neither needs escaping.
+const COMMENT_MARKERS: [&str; 3] = ["//", "#", ";"];
+
+/// Trims a line and drops its comment, returning `None` for a
+/// comment-only line.
+fn strip_comment(raw_line: &str) -> Option<&str> {
+    let line = raw_line.trim();
+    // Ignore line comments
+    if COMMENT_MARKERS
+        .iter()
+        .any(|marker| line.starts_with(marker))
+    {
+        return None;
+    }
+    // Ignore end-of-line comments
+    match COMMENT_MARKERS
+        .iter()
+        .filter_map(|marker| line.find(marker))
+        .min()
+    {
+        Some(comment_idx) => Some(line[..comment_idx].trim_end()),
+        None => Some(line),
+    }
+}
+
+/// Locates `text`, a slice of `raw_line`, within that line.
+fn line_span(line_idx: usize, raw_line: &str, text: &str) -> SourceSpan {
+    let start = text.as_ptr() as usize - raw_line.as_ptr() as usize;
+    SourceSpan {
+        line: line_idx + 1,
+        start,
+        end: start + text.trim_end().len(),
+    }
+}
+
+fn parse_line(
+    line: &str,
+    options: &AssembleOptions,
+    constants: &Constants,
+) -> AssemblerResult<Vec<Instruction>> {
+    if options.strict_format {
+        instruction::check_canonical_format(line)?;
+    }
+    if options.no_pseudo {
+        if let Some(pseudo) = instruction::pseudo_mnemonic(line) {
+            return Err(AssemblerError::PseudoInstruction(String::from(pseudo)));
+        }
+    }
+    Instruction::from_str_with(line, constants)
+}
+
+/// Reads every line up front, since constants need a pass of their own.
+/// Stops at the first read error, which is returned alongside.
+fn read_lines<R: BufRead>(reader: R) -> (Vec<String>, Option<Diagnostic>) {
+    let mut lines = Vec::new();
+    for (line_idx, raw_line) in reader.lines().enumerate() {
+        match raw_line {
+            Ok(raw_line) => lines.push(raw_line),
+            Err(err) => {
+                let diag = Diagnostic {
+                    span: SourceSpan {
+                        line: line_idx + 1,
+                        ..SourceSpan::default()
+                    },
+                    error: err.into(),
+                };
+                return (lines, Some(diag));
+            }
+        }
+    }
+    (lines, None)
+}
+
+/// First pass: collects every `.equ` so constants can be used ahead of
+/// their definition. As with labels, redefining a constant with another
+/// value is an error; repeating the same value only warns.
+fn collect_constants(
+    lines: &[String],
+    diagnostics: &mut Vec<Diagnostic>,
+    warnings: &mut Vec<Warning>,
+) -> Constants {
+    let mut constants = Constants::new();
+    for (line_idx, raw_line) in lines.iter().enumerate() {
+        let line = match strip_comment(raw_line) {
+            Some(line) => line,
+            None => continue,
+        };
+        let (name, value_str) = match detect_equ(line) {
+            Some(equ) => equ,
+            None => continue,
+        };
+        let span = line_span(line_idx, raw_line, line);
+        let value = match instruction::parse_value(value_str, &constants) {
+            Ok(value) => value,
+            Err(error) => {
+                diagnostics.push(Diagnostic { span, error });
+                continue;
+            }
+        };
+        match constants.entry(String::from(name)) {
+            Entry::Occupied(entry) if *entry.get() != value => diagnostics.push(Diagnostic {
+                span,
+                error: AssemblerError::DuplicateConstant(String::from(name)),
+            }),
+            Entry::Occupied(_) => warnings.push(Warning {
+                span,
+                message: format!("constant `{}` is redefined with the same value", name),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
+    constants
+}
+
+/// Recognises a `.equ NAME, value` directive line.
+fn detect_equ(line: &str) -> Option<(&str, &str)> {
+    lazy_static! {
+        static ref EQU_RE: Regex =
+            Regex::new(r"^\.equ[ \t]+([a-zA-Z_][a-zA-Z0-9_]*)[ \t]*,[ \t]*(.+?)[ \t]*$").unwrap();
+    }
+    let caps = EQU_RE.captures(line)?;
+    Some((caps.get(1)?.as_str(), caps.get(2)?.as_str()))
+}
+
+/// Recognises a `// expected-crc=0x1234abcd` (or `;`) comment line.
+fn detect_expected_crc(line: &str) -> Option<u32> {
+    lazy_static! {
+        static ref CRC_RE: Regex = Regex::new(
+            r"^[ \t]*(?://|;)[ \t]*expected-crc[ \t]*=[ \t]*0x([0-9a-fA-F]{1,8})[ \t]*$"
+        )
+        .unwrap();
+    }
+    CRC_RE
+        .captures(line)
+        .and_then(|caps| u32::from_str_radix(&caps[1], 16).ok())
+}
+
+/// Recognises a `.entry <label>` or `.global <label>` directive line.
+fn detect_entry(line: &str) -> Option<&str> {
+    lazy_static! {
+        static ref ENTRY_RE: Regex =
+            Regex::new(r"^\.(?:entry|global)[ \t]+([a-zA-Z0-9_.]+)[ \t]*$").unwrap();
+    }
+    ENTRY_RE
+        .captures(line)
+        .and_then(|caps| caps.get(1))
+        .map(|label| label.as_str())
+}
+
+/// Recognises a `.org`, `.space` or `.align` directive line, returning
+/// the directive name and its operand.
+fn detect_layout(line: &str) -> Option<(&str, &str)> {
+    lazy_static! {
+        static ref LAYOUT_RE: Regex =
+            Regex::new(r"^\.(org|space|align)[ \t]+([^ \t]+)[ \t]*$").unwrap();
+    }
+    let caps = LAYOUT_RE.captures(line)?;
+    Some((caps.get(1)?.as_str(), caps.get(2)?.as_str()))
+}
+
+/// Applies a layout directive by padding with zero words:
+/// - `.org addr` moves the origin before any instruction and pads up to
+///   `addr` after that, so it can't lie behind the code already placed
+///   or an origin an earlier `.org` set
+/// - `.space n` reserves `n` words
+/// - `.align n` pads up to the next 2^n-word boundary
+#[allow(clippy::too_many_arguments)]
+fn apply_layout(
+    directive: &str,
+    operand: &str,
+    origin: &mut u32,
+    origin_set: &mut bool,
+    instrs: &mut Vec<Instruction>,
+    spans: &mut Vec<SourceSpan>,
+    padding: &mut Vec<Padding>,
+    span: SourceSpan,
+    options: &AssembleOptions,
+) -> AssemblerResult<()> {
+    if directive == "org" && instrs.is_empty() {
+        let value = instruction::parse_address(operand)?;
+        if !value.is_multiple_of(4) {
+            return Err(AssemblerError::UnalignedOrg(value));
+        }
+        if *origin_set && value < *origin {
+            return Err(AssemblerError::OrgBackwards {
+                addr: value,
+                current: *origin,
+            });
+        }
+        *origin = value;
+        *origin_set = true;
+        return Ok(());
+    }
+    let len = padding_len(directive, operand, *origin, instrs.len(), options)?;
+    padding.push(Padding {
+        start: instrs.len(),
+        len,
+        directive: String::from(directive),
+        operand: String::from(operand),
+        span,
+    });
+    push_padding(instrs, spans, len, span)
+}
+
+/// Zero words a layout directive needs when it falls at word `start` of
+/// an image placed at `origin`. The padding must fit the configured
+/// memory and the address space.
+fn padding_len(
+    directive: &str,
+    operand: &str,
+    origin: u32,
+    start: usize,
+    options: &AssembleOptions,
+) -> AssemblerResult<usize> {
+    let value = instruction::parse_address(operand)?;
+    let current = word_addr(origin, start)?;
+    let padding = match directive {
+        "org" => {
+            if !value.is_multiple_of(4) {
+                return Err(AssemblerError::UnalignedOrg(value));
+            }
+            if value < current {
+                return Err(AssemblerError::OrgBackwards {
+                    addr: value,
+                    current,
+                });
+            }
+            (value - current) / 4
+        }
+        "space" => value,
+        "align" => {
+            // A 2^30-word boundary no longer fits a 32-bit address
+            if value >= 30 {
+                return Err(AssemblerError::InvalidNumber(String::from(operand)));
+            }
+            let boundary = 4 << value;
+            (boundary - current % boundary) % boundary / 4
+        }
+        _ => unreachable!("unexpected layout directive `{}`", directive),
+    };
+    let len = padding as usize;
+    let needed = start + len;
+    if let Some(depth) = options.depth {
+        if needed > depth {
+            return Err(AssemblerError::ImageTooLarge { needed, depth });
+        }
+    }
+    if let Some(size) = options.image_size {
+        let needed = needed * options.word_bytes();
+        if needed > size {
+            return Err(AssemblerError::ImageSizeExceeded { needed, size });
+        }
+    }
+    // The last padding word still needs an address
+    if len > 0 {
+        word_addr(origin, needed - 1)?;
+    }
+    Ok(len)
+}
+
+/// Byte address of word `idx` in an image placed at `origin`.
+fn word_addr(origin: u32, idx: usize) -> AssemblerResult<u32> {
+    let addr = origin as u64 + 4 * idx as u64;
+    u32::try_from(addr).map_err(|_| AssemblerError::AddressOutOfRange(addr))
+}
+
+/// Appends `len` zero words, failing cleanly if they can't be allocated.
+fn push_padding(
+    instrs: &mut Vec<Instruction>,
+    spans: &mut Vec<SourceSpan>,
+    len: usize,
+    span: SourceSpan,
+) -> AssemblerResult<()> {
+    let too_large = |_| AssemblerError::PaddingTooLarge(len);
+    instrs.try_reserve(len).map_err(too_large)?;
+    spans.try_reserve(len).map_err(too_large)?;
+    instrs.extend((0..len).map(|_| Instruction::Data { value: 0 }));
+    spans.extend((0..len).map(|_| span));
+    Ok(())
+}
+
+/// Recognises a `.comment "text"` directive line.
+fn detect_comment(line: &str) -> Option<&str> {
+    lazy_static! {
+        static ref COMMENT_RE: Regex =
+            Regex::new(r#"^[ \t]*\.comment[ \t]+"([^"]*)"[ \t]*$"#).unwrap();
+    }
+    COMMENT_RE
+        .captures(line)
+        .and_then(|caps| caps.get(1))
+        .map(|text| text.as_str())
+}
+
+/// Several comments on one instruction are joined in source order.
+fn add_comment(comments: &mut HashMap<usize, String>, idx: usize, text: &str) {
+    comments
+        .entry(idx)
+        .and_modify(|existing| {
+            existing.push_str("; ");
+            existing.push_str(text);
+        })
+        .or_insert_with(|| String::from(text));
+}
+
+/// Recognises a `.range label, count` directive, which labels a block of
+/// `count` reserved words, returning the label and the count.
+fn detect_range(line: &str) -> Option<(String, &str)> {
+    lazy_static! {
+        static ref RANGE_RE: Regex =
+            Regex::new(r"^\.range[ \t]+(\.?[a-zA-Z0-9_]+)[ \t]*,[ \t]*([^ \t]+)[ \t]*$").unwrap();
+    }
+    let caps = RANGE_RE.captures(line)?;
+    Some((String::from(&caps[1]), caps.get(2)?.as_str()))
+}
+
+/// Points `label` at instruction `idx`. The first definition wins so
+/// later references stay stable.
+fn define_label(
+    labels: &mut HashMap<String, usize>,
+    label_spans: &mut HashMap<String, SourceSpan>,
+    label: String,
+    idx: usize,
+    span: SourceSpan,
+) -> AssemblerResult<()> {
+    match labels.entry(label) {
+        Entry::Occupied(entry) => Err(AssemblerError::DuplicateLabel(entry.key().clone())),
+        Entry::Vacant(entry) => {
+            label_spans.insert(entry.key().clone(), span);
+            entry.insert(idx);
+            Ok(())
+        }
+    }
+}
+
+/// Splits a leading `name:` off the line, returning the label and
+/// whatever instruction follows it on the same line.
+fn detect_label(line: &str) -> Option<(String, &str)> {
+    lazy_static! {
+        static ref LABEL_RE: Regex = Regex::new(r"^(\.?[a-zA-Z0-9_]+):").unwrap();
+    }
+    let caps = LABEL_RE.captures(line)?;
+    let rest = &line[caps.get(0).unwrap().end()..];
+    Some((String::from(&caps[1]), rest.trim()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use instruction::{AbsLabel, RelLabel};
+
+    fn parsed(instrs: Vec<Instruction>) -> ParsedAsm {
+        ParsedAsm {
+            spans: vec![SourceSpan::default(); instrs.len()],
+            instrs,
+            labels: HashMap::new(),
+            label_spans: HashMap::new(),
+            entry: None,
+            comments: HashMap::new(),
+            expected_crc: None,
+            constants: Constants::new(),
+            source: Vec::new(),
+            warnings: Vec::new(),
+            padding: Vec::new(),
+            options: AssembleOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_image_size_padding() {
+        let mut parsed_asm = parsed(vec![
+            Instruction::AddImm { rs: 8, imm: 1 },
+            Instruction::Add { rs: 8, rt: 9 },
+            Instruction::Xor { rs: 9, rt: 9 },
+        ]);
+        parsed_asm.options.image_size = Some(32);
+        let image = parsed_asm.image();
+        assert!(image.is_ok());
+        let image = image.unwrap();
+        assert_eq!(image.len(), 8);
+        assert_eq!(image[2].value(), 0x15290000);
+        assert!(image[3..].iter().all(|word| word.value() == 0));
+
+        parsed_asm.options.image_size = Some(8);
+        assert!(matches!(
+            parsed_asm.image(),
+            Err(AssemblerError::ImageSizeExceeded {
+                needed: 12,
+                size: 8
+            })
+        ));
+        parsed_asm.options.image_size = Some(30);
+        assert!(matches!(
+            parsed_asm.image(),
+            Err(AssemblerError::InvalidImageSize(30))
+        ));
+        // Padding counts the bytes of the declared word width
+        let options = AssembleOptions {
+            word_width: 16,
+            image_size: Some(8),
+            ..AssembleOptions::default()
+        };
+        assert!(parse_reader(".space 4\n".as_bytes(), &options).is_ok());
+        assert!(matches!(
+            parse_reader(".space 5\n".as_bytes(), &options),
+            Err(AssemblerError::ImageSizeExceeded {
+                needed: 10,
+                size: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn test_assigned_label_addrs() {
+        let mut parsed_asm = parsed(vec![
+            Instruction::AddImm { rs: 8, imm: 1 },
+            Instruction::Bz {
+                rs: 8,
+                label: RelLabel::from("Exit"),
+            },
+            Instruction::B {
+                label: AbsLabel::from("Exit"),
+            },
+        ]);
+        parsed_asm.labels.insert(String::from("Exit"), 3);
+        assert!(parsed_asm.assign_labels(0x40).is_ok());
+        let abs_label = parsed_asm.instrs[2].abs_label();
+        assert!(abs_label.is_some());
+        assert_eq!(abs_label.unwrap().addr(), Some(0x4C));
+        let rel_label = parsed_asm.instrs[1].rel_label();
+        assert!(rel_label.is_some());
+        assert_eq!(rel_label.unwrap().addr(), Some(1));
+        assert!(parsed_asm.instrs[0].abs_label().is_none());
+    }
+
+    #[test]
+    fn test_label_offset() {
+        let parsed_asm = resolved("\tb target + 4\ntarget:\n\taddi $t0, 1\n\tb target - 4\n");
+        let target = |instr: &Instruction| instr.abs_label().and_then(AbsLabel::addr);
+        assert_eq!(parsed_asm.labels["target"], 1);
+        assert_eq!(target(&parsed_asm.instrs[0]), Some(8));
+        assert_eq!(target(&parsed_asm.instrs[2]), Some(0));
+        assert_eq!(parsed_asm.successors(0), vec![2]);
+
+        let mut before_start = parse_reader(
+            "start:\n\tb start - 4\n".as_bytes(),
+            &AssembleOptions::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            before_start.assign_labels(0),
+            Err(AssemblerError::ImmediateOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_collect_all_errors() {
+        let source = "\taddi $t0, 1\n\taddx $t0, 1\n\tadd $t0, $q1\n\tb nowhere\n";
+        let errs = assemble_with_options(source, &AssembleOptions::default()).err();
+        assert!(errs.is_some());
+        let errs = errs.unwrap();
+        assert_eq!(errs.len(), 3);
+        assert!(matches!(&errs[0], AssemblerError::UnknownInstruction(name) if name == "addx"));
+        assert!(matches!(&errs[1], AssemblerError::UnknownRegister(name) if name == "$q1"));
+        assert!(matches!(&errs[2], AssemblerError::UndefinedLabel(name) if name == "nowhere"));
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default()).err();
+        let lines: Vec<_> = diags.unwrap().iter().map(|diag| diag.span.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+
+        let options = AssembleOptions {
+            fail_fast: true,
+            ..AssembleOptions::default()
+        };
+        let errs = assemble_with_options(source, &options).err();
+        assert!(errs.is_some());
+        let errs = errs.unwrap();
+        assert_eq!(errs.len(), 1);
+        assert!(matches!(&errs[0], AssemblerError::UnknownInstruction(_)));
+
+        let source = "\taddi $t0, 1\n\n// setup done\n\t; ignored\n\tb x\nx:\n";
+        let parsed_asm = assemble_with_options(source, &options);
+        assert!(parsed_asm.is_ok());
+        assert_eq!(parsed_asm.unwrap().instrs.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_blank_lines() {
+        let source = "\taddi $t0, 1\n\n   \n\tb x\nx:\n";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        assert_eq!(parsed_asm.unwrap().instrs.len(), 2);
+        let words = assemble_str(source);
+        assert!(words.is_ok());
+        assert_eq!(words.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_assign_undefined_label() {
+        let mut parsed_asm =
+            parse_reader("\tb nowhere\n".as_bytes(), &AssembleOptions::default()).unwrap();
+        assert!(matches!(
+            parsed_asm.assign_labels(0),
+            Err(AssemblerError::UndefinedLabel(label)) if label == "nowhere"
+        ));
+    }
+
+    #[test]
+    fn test_assemble_report() {
+        let source = "main:\n\taddi $t0, 1\n\tb main\n\tadd $t0, $t0\nend:\n";
+        let report = assemble_report(source, &AssembleOptions::default());
+        assert!(report.errors.is_empty());
+        assert_eq!(report.words, vec![0x09000001, 0x38000000, 0x01080000]);
+        assert_eq!(
+            report.symbols,
+            vec![(String::from("main"), 0), (String::from("end"), 12)]
+        );
+        assert_eq!(
+            report.warnings,
+            vec![Warning {
+                span: SourceSpan {
+                    line: 4,
+                    start: 1,
+                    end: 13
+                },
+                message: String::from("unreachable instruction"),
+            }]
+        );
+        assert_eq!(
+            report.mnemonic_stats,
+            vec![("add", 1), ("addi", 1), ("b", 1)]
+        );
+        assert_eq!(
+            report.register_stats,
+            vec![RegisterUsage {
+                reg: 8,
+                reads: 3,
+                writes: 2
+            }]
+        );
+
+        let report = assemble_report("\tb nowhere\n", &AssembleOptions::default());
+        assert!(report.words.is_empty());
+        assert_eq!(report.errors.len(), 1);
+
+        let source = ".equ X, 1\n.equ X, 1\n\tadd $t0, $t1\n\tadd $t2, $t0\nadd:\n";
+        let options = AssembleOptions {
+            fix_hazards: true,
+            ..AssembleOptions::default()
+        };
+        let report = assemble_report(source, &options);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.words, vec![0x01090000, 0, 0x01480000]);
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .map(|warning| (warning.span.line, warning.message.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (2, "constant `X` is redefined with the same value"),
+                (5, "label `add` shadows a mnemonic name"),
+                (0, "inserted 1 nop(s) to resolve hazards"),
+            ]
+        );
+
+        let options = AssembleOptions {
+            strict: true,
+            require_entry: true,
+            ..AssembleOptions::default()
+        };
+        let report = assemble_report(source, &options);
+        assert!(report.words.is_empty());
+        let errors: Vec<_> = report.errors.iter().map(|diag| &diag.error).collect();
+        assert!(matches!(errors[0], AssemblerError::MissingEntry));
+        assert!(matches!(errors[1], AssemblerError::ShadowingLabel { .. }));
+
+        let report = assemble_report(
+            "// expected-crc=0x0\n\taddi $t0, 1\n",
+            &AssembleOptions::default(),
+        );
+        assert!(matches!(
+            report.errors[0].error,
+            AssemblerError::ChecksumMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_label_with_instruction() {
+        let source = "start: add $t0, $a0 // copy\nLoop:\tb start\n";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        let parsed_asm = parsed_asm.unwrap();
+        assert_eq!(parsed_asm.labels["start"], 0);
+        assert_eq!(parsed_asm.labels["Loop"], 1);
+        assert_eq!(parsed_asm.instrs[0], Instruction::Add { rs: 8, rt: 4 });
+        assert_eq!(
+            parsed_asm.spans[0],
+            SourceSpan {
+                line: 1,
+                start: 7,
+                end: 19
+            }
+        );
+        assert_eq!(parsed_asm.instrs.len(), 2);
+        let (_, diagnostics) = assemble_lenient(source, &AssembleOptions::default());
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_comment_styles() {
+        let expected = resolved("start:\n\taddi $t0, 1\n\tb start\n");
+        for marker in ["//", "#", ";"] {
+            let source = format!(
+                "{m} whole-line comment\nstart:\t{m} label\n\taddi $t0, 1 {m} bump\n  {m} indented\n\tb start{m}loop\n",
+                m = marker
+            );
+            let parsed_asm = resolved(&source);
+            assert_eq!(parsed_asm.instrs, expected.instrs);
+            assert_eq!(parsed_asm.labels, expected.labels);
+        }
+    }
+
+    #[test]
+    fn test_org() {
+        let plain = resolved("start:\n\taddi $t0, 1\n\tb start\n");
+        let moved = resolved(".org 0x100\nstart:\n\taddi $t0, 1\n\tb start\n");
+        assert_eq!(moved.options.base_addr, 0x100);
+        let target = |parsed_asm: &ParsedAsm| match parsed_asm.instrs.last().unwrap() {
+            Instruction::B { label } => label.addr().unwrap(),
+            instr => panic!("expected a branch, found {:?}", instr),
+        };
+        assert_eq!(target(&moved), target(&plain) + 0x100);
+
+        let padded = resolved("\taddi $t0, 1\n.org 0xc\nend:\n\tb end\n");
+        assert_eq!(padded.instrs.len(), 4);
+        assert_eq!(padded.instrs[1], Instruction::Data { value: 0 });
+        assert_eq!(padded.labels["end"], 3);
+        assert_eq!(target(&padded), 0xc);
+
+        let backwards = parse_reader(
+            ".org 0x10\n\taddi $t0, 1\n\taddi $t0, 1\n.org 0x14\n".as_bytes(),
+            &AssembleOptions::default(),
+        );
+        assert!(matches!(
+            backwards,
+            Err(AssemblerError::OrgBackwards {
+                addr: 0x14,
+                current: 0x18
+            })
+        ));
+        // An origin set before any code can't move back either
+        let reset = parse_reader(
+            ".org 0x100\n.org 0x80\n".as_bytes(),
+            &AssembleOptions::default(),
+        );
+        assert!(matches!(
+            reset,
+            Err(AssemblerError::OrgBackwards {
+                addr: 0x80,
+                current: 0x100
+            })
+        ));
+        let options = AssembleOptions {
+            base_addr: 0x200,
+            ..AssembleOptions::default()
+        };
+        let rebased = parse_reader(".org 0x100\n.org 0x180\n".as_bytes(), &options);
+        assert!(rebased.is_ok());
+        assert_eq!(rebased.unwrap().options.base_addr, 0x180);
+        assert!(matches!(
+            parse_reader(".org 0x102\n".as_bytes(), &AssembleOptions::default()),
+            Err(AssemblerError::UnalignedOrg(0x102))
+        ));
+    }
+
+    #[test]
+    fn test_data_words() {
+        let source = "\tb end\ntable:\n\t.word 0x1, 0x2, 0x3\nend:\n\taddi $t0, 1\n";
+        let parsed_asm = resolved(source);
+        assert_eq!(parsed_asm.labels["table"], 1);
+        assert_eq!(parsed_asm.labels["end"], 4);
+        match &parsed_asm.instrs[0] {
+            Instruction::B { label } => assert_eq!(label.addr(), Some(16)),
+            instr => panic!("expected a branch, found {:?}", instr),
+        }
+        let image = parsed_asm.image();
+        assert!(image.is_ok());
+        let values: Vec<_> = image.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(&values[1..4], &[1, 2, 3]);
+        assert_eq!(values.len(), 5);
+        assert!(parsed_asm.unreachable_instrs().is_empty());
+    }
+
+    #[test]
+    fn test_address_overflow() {
+        let source = ".org 0xFFFFFFFC\n\taddi $t0, 1\n\taddi $t0, 1\n";
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default()).err();
+        assert!(diags.is_some());
+        let diags = diags.unwrap();
+        assert_eq!(diags[0].span.line, 3);
+        assert!(matches!(
+            diags[0].error,
+            AssemblerError::AddressOutOfRange(0x1_0000_0000)
+        ));
+        let source = ".org 0xFFFFFFFC\n\taddi $t0, 1\nend:\n";
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default()).err();
+        assert!(diags.is_some());
+        assert_eq!(diags.unwrap()[0].span.line, 3);
+
+        let source = ".org 0x4000000\nx:\tb x\n";
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default()).err();
+        assert!(diags.is_some());
+        let diags = diags.unwrap();
+        assert_eq!(diags[0].span.line, 2);
+        assert!(matches!(
+            &diags[0].error,
+            AssemblerError::ImmediateOutOfRange { value, bits: 26 } if value == "x"
+        ));
+        assert!(resolved(".org 0x3FFFFFC\nx:\tb x\n").image().is_ok());
+    }
+
+    #[test]
+    fn test_word_labels() {
+        let source =
+            ".org 0x100\ntable:\n.word case0, case1, 7\ncase0:\n\tb case0\ncase1:\n\tb case1\n";
+        let parsed_asm = resolved(source);
+        let image = parsed_asm.image();
+        assert!(image.is_ok());
+        let values: Vec<_> = image.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(values[..3], [0x10C, 0x110, 7]);
+        let errs = assemble_with_options(".word nowhere\n", &AssembleOptions::default()).err();
+        assert!(errs.is_some());
+        assert!(matches!(
+            &errs.unwrap()[0],
+            AssemblerError::UndefinedLabel(name) if name == "nowhere"
+        ));
+    }
+
+    #[test]
+    fn test_space_and_align() {
+        let spaced = resolved("\taddi $t0, 1\n.space 3\nend:\n\tb end\n");
+        assert_eq!(spaced.labels["end"], 4);
+        assert!(spaced.instrs[1..4]
+            .iter()
+            .all(|instr| *instr == Instruction::Data { value: 0 }));
+        let image = spaced.image();
+        assert!(image.is_ok());
+        assert!(image.unwrap()[1..4].iter().all(|word| word.value() == 0));
+
+        let ranged = resolved(".org 0x100\n\taddi $t0, 1\n.range table, 4\nend:\n\t.word table\n");
+        assert_eq!(ranged.labels["table"], 1);
+        assert_eq!(ranged.labels["end"] - ranged.labels["table"], 4);
+        assert!(ranged.instrs[1..5]
+            .iter()
+            .all(|instr| *instr == Instruction::Data { value: 0 }));
+        let image = ranged.image();
+        assert!(image.is_ok());
+        assert_eq!(image.unwrap()[5].value(), 0x104);
+        assert!(matches!(
+            parse_reader("table:\n.range table, 2\n".as_bytes(), &AssembleOptions::default()),
+            Err(AssemblerError::DuplicateLabel(label)) if label == "table"
+        ));
+
+        let aligned = resolved(".org 0x104\n\taddi $t0, 1\n.align 2\nend:\n\tb end\n");
+        // 0x108 rounds up to the next 4-word boundary, 0x110
+        assert_eq!(aligned.labels["end"], 3);
+        match aligned.instrs.last().unwrap() {
+            Instruction::B { label } => assert_eq!(label.addr(), Some(0x110)),
+            instr => panic!("expected a branch, found {:?}", instr),
+        }
+        let already = resolved("\taddi $t0, 1\n\taddi $t0, 1\n.align 1\nend:\n\tb end\n");
+        assert_eq!(already.labels["end"], 2);
+        assert!(matches!(
+            parse_reader(".align 40\n".as_bytes(), &AssembleOptions::default()),
+            Err(AssemblerError::InvalidNumber(_))
+        ));
+
+        let options = AssembleOptions {
+            depth: Some(1024),
+            ..AssembleOptions::default()
+        };
+        assert!(matches!(
+            parse_reader("\taddi $t0, 1\n.space 0x10000000\n".as_bytes(), &options),
+            Err(AssemblerError::ImageTooLarge {
+                needed: 0x10000001,
+                depth: 1024
+            })
+        ));
+        // Only the top four words of the address space are left
+        let source = ".org 0xFFFFFFF0\n\taddi $t0, 1\n.space 4\n";
+        assert!(matches!(
+            parse_reader(source.as_bytes(), &AssembleOptions::default()),
+            Err(AssemblerError::AddressOutOfRange(0x1_0000_0000))
+        ));
+        let source = ".org 0xFFFFFFF0\n\taddi $t0, 1\n.space 3\n";
+        assert!(parse_reader(source.as_bytes(), &AssembleOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_equ_constants() {
+        let named = resolved(
+            "\taddi $sp, STACK_TOP\n\tlw $t0, -OFFSET($sp)\n.equ STACK_TOP, 0x3FC\n.equ OFFSET, 8\n",
+        );
+        let literal = resolved("\taddi $sp, 0x3FC\n\tlw $t0, -8($sp)\n");
+        assert_eq!(named.instrs, literal.instrs);
+        assert_eq!(named.image().unwrap(), literal.image().unwrap());
+        assert_eq!(named.constants["STACK_TOP"], 0x3FC);
+
+        let repeated = parse_reader(
+            ".equ X, 1\n.equ X, 1\n\taddi $t0, X\n".as_bytes(),
+            &AssembleOptions::default(),
+        );
+        assert!(repeated.is_ok());
+        let warnings = repeated.unwrap().warnings;
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].span.line, 2);
+        assert_eq!(
+            warnings[0].message,
+            "constant `X` is redefined with the same value"
+        );
+        let redefined = parse_reader(
+            ".equ X, 1\n.equ X, 2\n\taddi $t0, X\n".as_bytes(),
+            &AssembleOptions::default(),
+        );
+        assert!(matches!(
+            redefined,
+            Err(AssemblerError::DuplicateConstant(name)) if name == "X"
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let source = "start:\n\taddi $t0, 1\nstart:\n\tb start\n";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(matches!(
+            parsed_asm,
+            Err(AssemblerError::DuplicateLabel(label)) if label == "start"
+        ));
+        let (parsed_asm, diagnostics) = assemble_lenient(source, &AssembleOptions::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 3);
+        assert_eq!(parsed_asm.labels["start"], 0);
+    }
+
+    #[test]
+    fn test_render_diagnostic() {
+        let source = "start:\n    addx $t0, $t1\n\tb Exit\n";
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default());
+        assert!(diags.is_err());
+        let diags = diags.err().unwrap();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(
+            diags[0].render(source),
+            "  2 |     addx $t0, $t1\n    |     ^^^^ unknown instruction `addx`"
+        );
+        assert_eq!(diags[1].span.line, 3);
+        let source = "start:\n\tb Exit\n";
+        let diags = assemble_reader_located(source.as_bytes(), &AssembleOptions::default());
+        assert!(diags.is_err());
+        assert_eq!(
+            diags.err().unwrap()[0].render(source),
+            "  2 | \tb Exit\n    | \t  ^^^^ undefined label `Exit`"
+        );
+        let missing = Diagnostic::from(AssemblerError::MissingEntry);
+        assert_eq!(missing.render(source), missing.error.to_string());
+    }
+
+    #[test]
+    fn test_shadowing_labels() {
+        let source = "add:\n\taddi $t0, 1\nt0:\n.entry:\nLoop:\n\tb add\n";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default()).unwrap();
+        assert_eq!(
+            parsed_asm.shadowing_labels(),
+            vec![
+                (".entry", "directive"),
+                ("add", "mnemonic"),
+                ("t0", "register")
+            ]
+        );
+        let lenient = assemble_with_options(source, &AssembleOptions::default());
+        assert!(lenient.is_ok());
+        let warnings = lenient.unwrap().warnings;
+        let lines: Vec<_> = warnings.iter().map(|warning| warning.span.line).collect();
+        assert_eq!(lines, vec![4, 1, 3]);
+        assert_eq!(warnings[1].message, "label `add` shadows a mnemonic name");
+        let options = AssembleOptions {
+            strict: true,
+            ..AssembleOptions::default()
+        };
+        let errs = assemble_with_options(source, &options).err();
+        assert!(errs.is_some());
+        let errs = errs.unwrap();
+        assert_eq!(errs.len(), 3);
+        assert!(matches!(
+            &errs[1],
+            AssemblerError::ShadowingLabel { label, kind: "mnemonic" } if label == "add"
+        ));
+    }
+
+    #[test]
+    fn test_backward_label_addrs() {
+        let parsed_asm =
+            resolved("Loop:\n\taddi $t0, -1\n\tbnz $t0, Loop\n\tbcy Loop\n\tbl Loop\n");
+        let addrs: Vec<_> = parsed_asm.instrs[1..3]
+            .iter()
+            .map(|instr| instr.rel_label().and_then(RelLabel::addr))
+            .collect();
+        assert_eq!(addrs, vec![Some(0xFFFE), Some(0xFFFD)]);
+        let abs_label = parsed_asm.instrs[3].abs_label();
+        assert!(abs_label.is_some());
+        assert_eq!(abs_label.unwrap().addr(), Some(0));
+        assert_eq!(parsed_asm.instrs[3].get_label_name(), "Loop");
+    }
+
+    #[test]
+    fn test_branch_out_of_range() {
+        let far = "Top:\n.space 40000\n\tbz $t0, Top\n";
+        let mut parsed_asm = parse_reader(far.as_bytes(), &AssembleOptions::default()).unwrap();
+        assert!(matches!(
+            parsed_asm.assign_labels(0),
+            Err(AssemblerError::BranchOutOfRange { label, offset: -40001 }) if label == "Top"
+        ));
+        let diags = assemble_reader_located(far.as_bytes(), &AssembleOptions::default()).err();
+        assert!(diags.is_some());
+        assert_eq!(diags.unwrap()[0].span.line, 3);
+        // The furthest a 16-bit offset reaches back
+        let parsed_asm = resolved("Top:\n.space 32767\n\tbz $t0, Top\n");
+        let addr = parsed_asm.instrs[32767]
+            .rel_label()
+            .and_then(RelLabel::addr);
+        assert_eq!(addr, Some(0x8000));
+    }
+
+    #[test]
+    fn test_strict_format() {
+        let source = "add $t0,$t1";
+        let lenient = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(lenient.is_ok());
+        let strict_options = AssembleOptions {
+            strict_format: true,
+            ..AssembleOptions::default()
+        };
+        let strict = parse_reader(source.as_bytes(), &strict_options);
+        assert!(matches!(
+            strict,
+            Err(AssemblerError::NonCanonicalFormat { .. })
+        ));
+        let canonical = "Loop:\n\tadd $t0, $t1 // comment\n\tlw $t0, 4($sp)";
+        assert!(parse_reader(canonical.as_bytes(), &strict_options).is_ok());
+    }
+
+    #[test]
+    fn test_missing_labels() {
+        let source = "Loop:\n\tbz $t0, Exit\n\tb Loop\n\tbl Missing";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        let errs = parsed_asm.unwrap().check_labels();
+        assert_eq!(errs.len(), 2);
+        assert!(matches!(&errs[0], AssemblerError::UndefinedLabel(name) if name == "Exit"));
+        assert!(matches!(&errs[1], AssemblerError::UndefinedLabel(name) if name == "Missing"));
+    }
+
+    #[test]
+    fn test_instr_spans() {
+        let source = "// header\nStart:\n    addi $t0, 1 // one\n\tpush $t0\n";
+        let parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        let parsed_asm = parsed_asm.unwrap();
+        let instrs = parsed_asm.instrs_with_spans();
+        assert_eq!(instrs.len(), 3);
+        assert_eq!(
+            instrs[1],
+            (
+                &Instruction::Sw {
+                    rt: 8,
+                    imm: 0,
+                    rs: 29
+                },
+                SourceSpan {
+                    line: 4,
+                    start: 1,
+                    end: 9
+                }
+            )
+        );
+        assert_eq!(instrs[2].1, instrs[1].1);
+        assert_eq!(
+            instrs[0].1,
+            SourceSpan {
+                line: 3,
+                start: 4,
+                end: 15
+            }
+        );
+    }
+
+    fn resolved(source: &str) -> ParsedAsm {
+        let parsed_asm = assemble_with_options(source, &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        parsed_asm.unwrap()
+    }
+
+    #[test]
+    fn test_programs_equal() {
+        let first = resolved("// Count up\nLoop:\n\taddi $t0, 1\n\tb Loop\n");
+        let second = resolved("Again:\n    addi   $t0,0x1 // same thing\n  b   Again");
+        assert_eq!(first.first_difference(&second).unwrap(), None);
+        let third = resolved("Loop:\n\taddi $t0, 2\n\tb Loop\n");
+        assert_eq!(first.first_difference(&third).unwrap(), Some(0));
+        let longer = resolved("Loop:\n\taddi $t0, 1\n\tb Loop\n\tb Loop\n");
+        assert_eq!(first.first_difference(&longer).unwrap(), Some(8));
+        let based = resolved(".org 0x100\nLoop:\n\taddi $t0, 1\n\tb Loop\n");
+        let moved = resolved(".org 0x100\nLoop:\n\taddi $t0, 1\n\tbl Loop\n");
+        assert_eq!(based.first_difference(&moved).unwrap(), Some(0x104));
+        let mut listing = Vec::new();
+        assert!(based.write_listing(&mut listing).is_ok());
+        assert!(String::from_utf8(listing)
+            .unwrap()
+            .contains("0x00000104  38000100  \tb Loop"));
+    }
+
+    #[test]
+    fn test_python_literal() {
+        let parsed_asm = resolved("Loop:\n\taddi $t2, 657\n\tb Loop\n");
+        let mut out = Vec::new();
+        assert!(parsed_asm
+            .write_python(&mut out, PythonLiteral::Bytes)
+            .is_ok());
+        let text = String::from_utf8(out).unwrap();
+        let hex = text
+            .strip_prefix("program = bytes.fromhex(\"")
+            .and_then(|rest| rest.strip_suffix("\")\n"));
+        assert!(hex.is_some());
+        let words: Vec<_> = hex
+            .unwrap()
+            .as_bytes()
+            .chunks(8)
+            .map(|chunk| u32::from_str_radix(std::str::from_utf8(chunk).unwrap(), 16).unwrap())
+            .collect();
+        assert_eq!(words, vec![0x09400291, 0x38000000]);
+
+        let mut out = Vec::new();
+        assert!(parsed_asm
+            .write_python(&mut out, PythonLiteral::List)
+            .is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "program = [\n    0x09400291,\n    0x38000000,\n]\n"
+        );
+    }
+
+    #[test]
+    fn test_nop_runs() {
+        let parsed_asm = resolved("addi $t0, 1\nnop\nnop\nnop\naddi $t0, 1\nnop\nadd $t0, $t0");
+        assert_eq!(parsed_asm.nop_runs(), vec![1..4]);
+        assert_eq!(parsed_asm.instrs.len(), 7);
+        assert!(resolved("nop\naddi $t0, 1\nnop").nop_runs().is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_instrs() {
+        let source = "main:\n\tbz $t0, Skip\n\tb main\n\taddi $t0, 1\n\txor $t1, $t1\nSkip:\n\tbl func\n\tb main\nfunc:\n\tbr $ra\n\tadd $t0, $t1";
+        let parsed_asm = resolved(source);
+        assert_eq!(parsed_asm.unreachable_instrs(), vec![2, 3, 7]);
+        let div = resolved("Loop:\n\tcomp $t3, $t1\n\tbltz $t3, Exit\n\tb Loop\nExit:\n");
+        assert!(div.unreachable_instrs().is_empty());
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let source = "Loop:\n\taddi $t0, 1\n\tb Loop\n";
+        let options = AssembleOptions {
+            depth: Some(1),
+            ..AssembleOptions::default()
+        };
+        let mut parsed_asm = parse_reader(source.as_bytes(), &options).unwrap();
+        assert!(parsed_asm.assign_labels(0).is_ok());
+        assert!(matches!(
+            parsed_asm.image(),
+            Err(AssemblerError::ImageTooLarge {
+                needed: 2,
+                depth: 1
+            })
+        ));
+        parsed_asm.options.depth = Some(2);
+        let exact = parsed_asm.image();
+        assert!(exact.is_ok());
+        assert_eq!(exact.unwrap().len(), 2);
+
+        parsed_asm.options.depth = Some(4);
+        let padded = parsed_asm.image();
+        assert!(padded.is_ok());
+        let values: Vec<_> = padded.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(values, vec![0x09000001, 0x38000000, 0, 0]);
+        let mut coe = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut coe).is_ok());
+        assert_eq!(
+            coe::validate(&String::from_utf8(coe).unwrap())
+                .unwrap()
+                .words,
+            4
+        );
+        parsed_asm.options.depth = Some(3);
+        let mut mif = Vec::new();
+        assert!(parsed_asm.write_mif_to(&mut mif).is_ok());
+        let mif = String::from_utf8(mif).unwrap();
+        assert!(mif.starts_with("DEPTH = 3;\n"));
+        assert!(mif.contains("    2 : 00000000;\n"));
+    }
+
+    #[test]
+    fn test_dump_expanded() {
+        let source = "main:\n\tpush $s0\n\tmov $s0, $a0\n\tbz $s0, done\ndone:\n";
+        let parsed_asm = resolved(source);
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_expanded(&mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main:\n\tsw $s0, 0($sp)\n\taddi $sp, -4\n\txor $s0, $s0\n\tadd $s0, $a0\n\tbz $s0, done\ndone:\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width_hex() {
+        let parsed_asm = parsed(vec![Instruction::AddImm { rs: 0, imm: 5 }]);
+        let mut out = Vec::new();
+        assert!(parsed_asm
+            .write_python(&mut out, PythonLiteral::List)
+            .is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "program = [\n    0x08000005,\n]\n"
+        );
+        let mut options = AssembleOptions::default();
+        assert_eq!(options.format_addr(5), "0x00000005");
+        options.addr_digits = Some(4);
+        assert_eq!(options.format_addr(5), "0x0005");
+    }
+
+    #[test]
+    fn test_no_pseudo() {
+        let source = "\taddi $t0, 5\n\tmov $t1, $t0\n";
+        assert!(parse_reader(source.as_bytes(), &AssembleOptions::default()).is_ok());
+        let options = AssembleOptions {
+            no_pseudo: true,
+            ..AssembleOptions::default()
+        };
+        assert!(matches!(
+            parse_reader(source.as_bytes(), &options),
+            Err(AssemblerError::PseudoInstruction(name)) if name == "mov"
+        ));
+    }
+
+    #[test]
+    fn test_data_hazards() {
+        let source = "\tadd $t0, $t1\n\tadd $t2, $t0\n\txor $t3, $t3\n\tsw $t0, 0($sp)\n";
+        let parsed_asm = resolved(source);
+        assert_eq!(
+            parsed_asm.data_hazards(1),
+            vec![Hazard {
+                producer: 0,
+                consumer: 1,
+                reg: 8
+            }]
+        );
+        assert_eq!(
+            parsed_asm.data_hazards(3),
+            vec![
+                Hazard {
+                    producer: 0,
+                    consumer: 1,
+                    reg: 8
+                },
+                Hazard {
+                    producer: 0,
+                    consumer: 3,
+                    reg: 8
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fix_hazards() {
+        let source = "\tadd $t0, $t1\nNext:\n\tadd $t2, $t0\n\tb Next\nEnd:\n";
+        let mut parsed_asm = parse_reader(source.as_bytes(), &AssembleOptions::default()).unwrap();
+        assert_eq!(parsed_asm.fix_hazards(2).unwrap(), 2);
+        assert_eq!(parsed_asm.instrs.len(), 5);
+        assert_eq!(parsed_asm.instrs[1], Instruction::NOP);
+        assert_eq!(parsed_asm.instrs[3], Instruction::Add { rs: 10, rt: 8 });
+        assert_eq!(parsed_asm.labels["Next"], 1);
+        assert_eq!(parsed_asm.labels["End"], 5);
+        assert!(parsed_asm.data_hazards(2).is_empty());
+        assert!(parsed_asm.assign_labels(0).is_ok());
+        assert_eq!(parsed_asm.instrs[4].abs_label().unwrap().addr(), Some(4));
+        assert_eq!(parsed_asm.fix_hazards(2).unwrap(), 0);
+
+        let options = AssembleOptions {
+            fix_hazards: true,
+            hazard_distance: 2,
+            ..AssembleOptions::default()
+        };
+        let parsed_asm = assemble_with_options(source, &options);
+        assert!(parsed_asm.is_ok());
+        let warnings = parsed_asm.unwrap().warnings;
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "inserted 2 nop(s) to resolve hazards");
+
+        // Code placed by `.org` stays put; the padding before it shrinks
+        let source = "\tadd $t0, $t1\n\tadd $t2, $t0\n.org 0x10\nhere:\n\tb here\n";
+        let parsed_asm = assemble_with_options(source, &options);
+        assert!(parsed_asm.is_ok());
+        let parsed_asm = parsed_asm.unwrap();
+        assert_eq!(parsed_asm.labels["here"], 4);
+        assert_eq!(parsed_asm.instrs.len(), 5);
+        assert_eq!(parsed_asm.instrs[4].abs_label().unwrap().addr(), Some(0x10));
+        let source = "\tadd $t0, $t1\n\tadd $t2, $t0\n.org 0x8\n\tadd $t0, $t0\n";
+        let errs = assemble_with_options(source, &options).err();
+        assert!(errs.is_some());
+        assert!(matches!(
+            errs.unwrap()[0],
+            AssemblerError::OrgBackwards {
+                addr: 8,
+                current: 16
+            }
+        ));
+    }
+
+    #[test]
+    fn test_assemble_with_options() {
+        let source = "Start:\n\t.word 1\n\t.word Start\n";
+        let options = AssembleOptions {
+            base_addr: 0x100,
+            word_width: 16,
+            image_size: Some(8),
+            ..AssembleOptions::default()
+        };
+        let parsed_asm = assemble_with_options(source, &options);
+        assert!(parsed_asm.is_ok());
+        let image = parsed_asm.unwrap().image();
+        assert!(image.is_ok());
+        let values: Vec<_> = image.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(values, vec![0x0001, 0x0100, 0, 0]);
+        let parsed_asm = assemble_with_options("\tadd $t0, $t1\n\tbr $ra\n", &options);
+        assert!(parsed_asm.is_ok());
+        let image = parsed_asm.unwrap().image();
+        assert!(image.is_ok());
+        let values: Vec<_> = image.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(values, vec![0x0109, 0x3FE0, 0, 0]);
+        let parsed_asm = assemble_with_options("\taddi $t0, 1\n", &options);
+        assert!(parsed_asm.is_ok());
+        assert!(matches!(
+            parsed_asm.unwrap().image(),
+            Err(AssemblerError::ImmediateOutOfRange { bits: 0, .. })
+        ));
+        let errs = assemble_with_options("\tb Nowhere\n", &options).err();
+        assert!(matches!(
+            errs.as_deref(),
+            Some([AssemblerError::UndefinedLabel(_)])
+        ));
+    }
+
+    #[test]
+    fn test_decimal_coe() {
+        let mut parsed_asm = parsed(vec![
+            Instruction::AddImm { rs: 10, imm: 657 },
+            Instruction::AddImm {
+                rs: 31,
+                imm: 0xFFFF,
+            },
+        ]);
+        parsed_asm.options.coe_radix = CoeRadix::Decimal { signed: false };
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "memory_initialization_radix=10;\nmemory_initialization_vector=\n155189905,\n199294975;\n"
+        );
+        parsed_asm.instrs = vec![
+            Instruction::Data { value: 657 },
+            Instruction::Data { value: 0xFFFF },
+        ];
+        parsed_asm.options.word_width = 16;
+        parsed_asm.options.coe_radix = CoeRadix::Decimal { signed: true };
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        assert!(String::from_utf8(out).unwrap().ends_with("\n657,\n-1;\n"));
+    }
+
+    #[test]
+    fn test_hex_coe() {
+        let mut parsed_asm = parsed(vec![
+            Instruction::AddImm { rs: 10, imm: 657 },
+            Instruction::AddImm {
+                rs: 31,
+                imm: 0xFFFF,
+            },
+        ]);
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "memory_initialization_radix=2;\nmemory_initialization_vector=\n\
+             00001001010000000000001010010001,\n\
+             00001011111000001111111111111111;\n"
+        );
+        parsed_asm.options.coe_radix = CoeRadix::Hex;
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n09400291,\n0be0ffff;\n"
+        );
+        assert_eq!(
+            coe::validate(&out).unwrap(),
+            coe::CoeSummary {
+                radix: 16,
+                words: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_assemble_str() {
+        let words = assemble_str("Loop:\n\taddi $t2, 657\n\tbnz $t2, Loop\n\tb Loop\n");
+        assert!(words.is_ok());
+        assert_eq!(words.unwrap(), vec![0x09400291, 0x4940FFFE, 0x38000000]);
+        assert!(matches!(
+            assemble_str("\tb Nowhere\n"),
+            Err(AssemblerError::UndefinedLabel(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let parsed_asm = parse_file("data/fibo.asm");
+        assert!(parsed_asm.is_ok());
+        let mut parsed_asm = parsed_asm.unwrap();
+        assert!(parsed_asm.check_labels().is_empty());
+        assert!(parsed_asm.assign_labels(0).is_ok());
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        let summary = coe::validate(&String::from_utf8(out).unwrap());
+        assert!(summary.is_ok());
+        assert_eq!(summary.unwrap().words, parsed_asm.instrs.len());
+    }
+
+    #[test]
+    fn test_readmem_output() {
+        let parsed_asm = resolved("Loop:\n\taddi $t2, 657\n\tb Loop\n");
+        let mut hex = Vec::new();
+        assert!(parsed_asm.write_readmemh(&mut hex).is_ok());
+        assert_eq!(String::from_utf8(hex).unwrap(), "09400291\n38000000\n");
+        let mut bin = Vec::new();
+        assert!(parsed_asm.write_readmemb(&mut bin).is_ok());
+        assert_eq!(
+            String::from_utf8(bin).unwrap(),
+            "00001001010000000000001010010001\n00111000000000000000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_c_header() {
+        let parsed_asm = resolved("Loop:\n\taddi $t2, 657\n\tb Loop\n");
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_c_header(&mut out, "boot_rom").is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "#ifndef BOOT_ROM_H
+#define BOOT_ROM_H
+
+#include <stdint.h>
+
+const uint32_t boot_rom[] = {
+    0x09400291, 0x38000000,
+};
+
+#define BOOT_ROM_LEN 2
+
+#endif /* BOOT_ROM_H */
+"
+        );
+        assert_eq!(c_identifier(Path::new("out/2nd-rom.h")), "_2nd_rom");
+    }
+
+    #[test]
+    fn test_reverse_image() {
+        let mut parsed_asm = resolved("Loop:\n\taddi $t2, 657\n\tadd $t0, $t2\n\tb Loop\n");
+        parsed_asm.options.reverse = true;
+        let image = parsed_asm.image();
+        assert!(image.is_ok());
+        let values: Vec<_> = image.unwrap().iter().map(|word| word.value()).collect();
+        assert_eq!(values, vec![0x38000000, 0x010a0000, 0x09400291]);
+    }
+
+    #[test]
+    fn test_reloaded_isa_table() {
+        let source = "Loop:\n\taddi $t2, 657\n\tlw $t0, 4($sp)\n\tbnz $t2, Loop\n";
+        let mut dumped = Vec::new();
+        assert!(OpcodeTable::default().write(&mut dumped).is_ok());
+        let table = OpcodeTable::parse(&String::from_utf8(dumped).unwrap()).unwrap();
+        let builtin = resolved(source);
+        let options = AssembleOptions {
+            isa: Some(table),
+            ..AssembleOptions::default()
+        };
+        let reloaded = assemble_with_options(source, &options);
+        assert!(reloaded.is_ok());
+        let reloaded = reloaded.unwrap();
+        assert_eq!(reloaded.image().unwrap(), builtin.image().unwrap());
+    }
+
+    #[test]
+    fn test_cfg_dot() {
+        let source =
+            "main:\n\taddi $t0, -1\n\tbz $t0, done\n\tadd $t1, $t0\n\tb main\ndone:\n\tbr $ra\n";
+        let parsed_asm = resolved(source);
+        assert_eq!(parsed_asm.basic_blocks(), vec![0..2, 2..4, 4..5]);
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_dot(&mut out).is_ok());
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b0 [label=\"main:\\n0x00000000-0x00000004\"];\n"));
+        assert!(dot
+            .contains("    b0 -> b1 [label=\"fallthrough\"];\n    b0 -> b2 [label=\"branch\"];\n"));
+        assert!(dot.contains("    b1 -> b0 [label=\"branch\"];\n"));
+        assert!(!dot.contains("b2 ->"));
+    }
+
+    #[test]
+    fn test_mif_output() {
+        let parsed_asm = resolved(
+            "Loop:\n\taddi $t2, 657\n\tbnz $t2, Loop\n\tadd $t0, $t2\n\tb Loop\n\tbr $ra\n",
+        );
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_mif_to(&mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "DEPTH = 8;
+WIDTH = 32;
+ADDRESS_RADIX = HEX;
+DATA_RADIX = HEX;
+CONTENT BEGIN
+    0 : 09400291;
+    1 : 4940fffe;
+    2 : 010a0000;
+    3 : 38000000;
+    4 : 3fe00000;
+    [5..7] : 00000000;
+END;
+"
+        );
+    }
+
+    #[test]
+    fn test_mif_runs() {
+        let parsed_asm = resolved("\t.word 7, 7, 7, 1\n\tnop\n\t.word 0\n");
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_mif_to(&mut out).is_ok());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(
+            "CONTENT BEGIN\n    [0..2] : 00000007;\n    3 : 00000001;\n    [4..7] : 00000000;\nEND;\n"
+        ));
+        assert_eq!(mif::validate(&out).unwrap().words, 8);
+    }
+
+    #[test]
+    fn test_written_coe_validates() {
+        let parsed_asm = resolved("Loop:\n\taddi $t0, 1\n\tbz $t0, Loop\n\tb Loop\n");
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut out).is_ok());
+        let summary = coe::validate(&String::from_utf8(out).unwrap());
+        assert!(summary.is_ok());
+        assert_eq!(summary.unwrap().words, 3);
+        let mut out = Vec::new();
+        assert!(parsed_asm.write_mif_to(&mut out).is_ok());
+        let summary = mif::validate(&String::from_utf8(out).unwrap());
+        assert!(summary.is_ok());
+        assert_eq!(summary.unwrap().words, 4);
+
+        let empty = resolved("// nothing yet\n");
+        let mut out = Vec::new();
+        assert!(empty.write_coe_to(&mut out).is_ok());
+        let summary = coe::validate(&String::from_utf8(out).unwrap());
+        assert!(summary.is_ok());
+        assert_eq!(summary.unwrap().words, 0);
+    }
+
+    #[test]
+    fn test_usage_stats() {
+        let parsed_asm = resolved("\tadd $t0, $t1\n\tsw $t0, 4($sp)\n\tadd $t2, $t2\n");
+        assert_eq!(parsed_asm.mnemonic_stats(), vec![("add", 2), ("sw", 1)]);
+        assert_eq!(
+            parsed_asm.register_stats(),
+            vec![
+                RegisterUsage {
+                    reg: 8,
+                    reads: 2,
+                    writes: 1
+                },
+                RegisterUsage {
+                    reg: 10,
+                    reads: 2,
+                    writes: 1
+                },
+                RegisterUsage {
+                    reg: 9,
+                    reads: 1,
+                    writes: 0
+                },
+                RegisterUsage {
+                    reg: 29,
+                    reads: 1,
+                    writes: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lenient_recovers_labels() {
+        let source =
+            "main:\n\taddi $t0, 1\n\tbogus $t0\n\tbz $t0, done\n\tb main\ndone:\n\tbr $ra\n";
+        let (parsed_asm, diagnostics) = assemble_lenient(source, &AssembleOptions::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 3);
+        assert!(matches!(
+            diagnostics[0].error,
+            AssemblerError::UnknownInstruction(_)
+        ));
+        assert_eq!(parsed_asm.instrs.len(), 5);
+        assert_eq!(parsed_asm.labels["main"], 0);
+        assert_eq!(parsed_asm.labels["done"], 4);
+        assert_eq!(parsed_asm.instrs[1], Instruction::NOP);
+        assert_eq!(parsed_asm.instrs[2].rel_label().unwrap().addr(), Some(1));
+        assert_eq!(parsed_asm.instrs[3].abs_label().unwrap().addr(), Some(0));
+    }
+
+    #[test]
+    fn test_expected_crc() {
+        let source = "Loop:\n\taddi $t0, 1\n\tb Loop\n";
+        let crc = resolved(source).crc32();
+        assert!(crc.is_ok());
+        let crc = crc.unwrap();
+        let matching = format!("// expected-crc={:#010x}\n{}", crc, source);
+        assert!(assemble_with_options(&matching, &AssembleOptions::default()).is_ok());
+        let mismatching = format!("; expected-crc={:#010x}\n{}", crc ^ 1, source);
+        let errs = assemble_with_options(&mismatching, &AssembleOptions::default()).err();
+        assert!(matches!(
+            errs.as_deref(),
+            Some([AssemblerError::ChecksumMismatch { expected, actual }])
+                if *expected == crc ^ 1 && *actual == crc
+        ));
+    }
+
+    #[test]
+    fn test_write_bin() {
+        let parsed_asm = resolved("\taddi $t0, 1\n\tadd $t0, $t1\n");
+        let mut little = Vec::new();
+        assert!(parsed_asm
+            .write_bin(&mut little, Endianness::Little)
+            .is_ok());
+        assert_eq!(little, vec![0x01, 0x00, 0x00, 0x09, 0x00, 0x00, 0x09, 0x01]);
+        let mut big = Vec::new();
+        assert!(parsed_asm.write_bin(&mut big, Endianness::Big).is_ok());
+        assert_eq!(big, vec![0x09, 0x00, 0x00, 0x01, 0x01, 0x09, 0x00, 0x00]);
+        assert_eq!("little".parse(), Ok(Endianness::Little));
+        assert!("middle".parse::<Endianness>().is_err());
+    }
+
+    #[test]
+    fn test_bin_header() {
+        let mut parsed_asm = resolved("\taddi $t0, 1\n\tadd $t0, $t1\n\tnop\n");
+        let raw = parsed_asm.bin_bytes(Endianness::Big).unwrap();
+        parsed_asm.options.bin_header = Some(String::from("a_rather_long_program_name.asm"));
+        let bytes = parsed_asm.bin_bytes(Endianness::Big);
+        assert!(bytes.is_ok());
+        let bytes = bytes.unwrap();
+        assert_eq!(bytes.len(), BIN_HEADER_BYTES + 12);
+        assert_eq!(bytes[..4], BIN_HEADER_MAGIC);
+        assert_eq!(bytes[4..8], BIN_HEADER_VERSION.to_be_bytes());
+        assert_eq!(bytes[8..12], 3_u32.to_be_bytes());
+        assert_eq!(&bytes[12..BIN_HEADER_BYTES], b"a_rather_long_progra");
+        assert_eq!(bytes[BIN_HEADER_BYTES..], raw);
+
+        parsed_asm.options.bin_header = Some(String::from("fibo.asm"));
+        let bytes = parsed_asm.bin_bytes(Endianness::Little).unwrap();
+        let name = &bytes[12..BIN_HEADER_BYTES];
+        assert_eq!(name[..8], *b"fibo.asm");
+        assert!(name[8..].iter().all(|&byte| byte == 0));
+        assert_eq!(
+            bytes[BIN_HEADER_BYTES..BIN_HEADER_BYTES + 4],
+            [0x01, 0x00, 0x00, 0x09]
+        );
+    }
+
+    #[test]
+    fn test_format_dispatch() {
+        let parsed_asm = resolved("\taddi $t0, 1\n");
+        let dispatched = |name: &str| {
+            let format = name.parse();
+            assert!(format.is_ok(), "`{}` should parse", name);
+            let mut out = Vec::new();
+            assert!(parsed_asm
+                .write_to(format.unwrap(), &mut out, "rom")
+                .is_ok());
+            out
+        };
+        let direct = |write: &dyn Fn(&mut Vec<u8>) -> AssemblerResult<()>| {
+            let mut out = Vec::new();
+            assert!(write(&mut out).is_ok());
+            out
+        };
+        let coe = direct(&|out| parsed_asm.write_coe_to(out));
+        assert!(String::from_utf8_lossy(&coe).contains("00001001000000000000000000000001;"));
+        assert_eq!(dispatched("coe"), coe);
+        assert_eq!(
+            dispatched("mif"),
+            direct(&|out| parsed_asm.write_mif_to(out))
+        );
+        for name in ["readmemh", "hex", "verilog"] {
+            assert_eq!(dispatched(name), b"09000001\n");
+        }
+        for name in ["readmemb", "binb"] {
+            assert_eq!(dispatched(name), b"00001001000000000000000000000001\n");
+        }
+        let header = direct(&|out| parsed_asm.write_c_header(out, "rom"));
+        assert_eq!(dispatched("c-header"), header);
+        assert_eq!(dispatched("c"), header);
+        assert_eq!(
+            dispatched("python-list"),
+            direct(&|out| parsed_asm.write_python(out, PythonLiteral::List))
+        );
+        assert_eq!(dispatched("bin"), vec![0x09, 0x00, 0x00, 0x01]);
+        assert!(OutputFormat::NAMES
+            .iter()
+            .all(|name| name.parse::<OutputFormat>().is_ok()));
+        assert!(matches!(
+            "elf".parse::<OutputFormat>(),
+            Err(msg) if msg.contains("`elf`") && msg.contains("coe, mif")
+        ));
+    }
+
+    #[test]
+    fn test_emit_tcp() {
+        use std::{io::Read, net::TcpListener, thread};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let receiver = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+        let mut parsed_asm = resolved("\taddi $t0, 1\n\tadd $t0, $t1\n");
+        parsed_asm.options.bin_header = Some(String::from("boot.asm"));
+        let bytes = parsed_asm.bin_bytes(Endianness::Big);
+        assert!(bytes.is_ok());
+        let bytes = bytes.unwrap();
+        assert!(emit_tcp(&addr, &bytes).is_ok());
+        let received = receiver.join().unwrap();
+        assert_eq!(received, bytes);
+        assert_eq!(received[..4], BIN_HEADER_MAGIC);
+        assert_eq!(
+            received[BIN_HEADER_BYTES..],
+            [0x09, 0x00, 0x00, 0x01, 0x01, 0x09, 0x00, 0x00]
+        );
+
+        // Nothing listens on a port we just released
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        assert!(matches!(
+            emit_tcp(&addr, &bytes),
+            Err(AssemblerError::EmitFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_instruction_comments() {
+        let source = "Loop:\n\t.comment \"bump the counter\"\n\taddi $t0, 1\n\t.comment \"again: forever\"\n\tb Loop\n";
+        let parsed_asm = resolved(source);
+        assert_eq!(parsed_asm.labels["Loop"], 0);
+        let mut listing = Vec::new();
+        assert!(parsed_asm.write_expanded(&mut listing).is_ok());
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            "Loop:\n\taddi $t0, 1\t// bump the counter\n\tb Loop\t// again: forever\n"
+        );
+        let mut coe = Vec::new();
+        assert!(parsed_asm.write_coe_to(&mut coe).is_ok());
+        let coe = String::from_utf8(coe).unwrap();
+        assert!(coe.contains("=\n; bump the counter\n00001001"));
+        assert!(coe::validate(&coe).is_ok());
+    }
+
+    #[test]
+    fn test_write_listing() {
+        let parsed_asm = assemble("data/fibo.asm", &AssembleOptions::default());
+        assert!(parsed_asm.is_ok());
+        let mut listing = Vec::new();
+        assert!(parsed_asm.unwrap().write_listing(&mut listing).is_ok());
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            include_str!("../data/fibo.lst")
+        );
+
+        let parsed_asm = resolved("loop:\n\tbz $t0, done\n\tbz $t0, loop\ndone:\n");
+        let mut listing = Vec::new();
+        assert!(parsed_asm.write_listing(&mut listing).is_ok());
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<_> = listing.lines().collect();
+        assert!(lines[1].ends_with("\tbz $t0, done  # -> 0x00000008 (+1)"));
+        assert!(lines[2].ends_with("\tbz $t0, loop  # -> 0x00000000 (-2)"));
+
+        let parsed_asm = resolved(".equ N, 2\nstart:\n\n.word N, 3\n");
+        let mut listing = Vec::new();
+        assert!(parsed_asm.write_listing(&mut listing).is_ok());
+        assert_eq!(
+            String::from_utf8(listing).unwrap(),
+            "0x00000000            .equ N, 2\n\
+             0x00000000            start:\n\
+             0x00000000\n\
+             0x00000000  00000002  .word N, 3\n\
+             0x00000004  00000003\n"
+        );
+    }
+
+    #[test]
+    fn test_write_map() {
+        let source = ".org 0x10\nstart:\n\taddi $t0, 1\nloop:\nagain:\n\tb loop\nend:\n";
+        let mut parsed_asm = resolved(source);
+        parsed_asm.options.addr_digits = Some(4);
+        let mut map = Vec::new();
+        assert!(parsed_asm.write_map(&mut map).is_ok());
+        let map = String::from_utf8(map).unwrap();
+        let segments: Vec<Vec<_>> = map.lines().map(|line| line.split("  ").collect()).collect();
+        let names: Vec<_> = segments
+            .iter()
+            .map(|fields| (fields[0], fields[1]))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("0x0010", "start"),
+                ("0x0014", "again"),
+                ("0x0014", "loop"),
+                ("0x0018", "end")
+            ]
+        );
+        let sizes: Vec<_> = segments.iter().map(|fields| fields[2]).collect();
+        assert_eq!(sizes, vec!["1", "1", "1", "0"]);
+
+        // Bitwise CRC-32 with the polynomial in its normal, unreflected form
+        let reference = |bytes: &[u8]| {
+            let mut crc = !0u32;
+            for &byte in bytes {
+                crc ^= (byte.reverse_bits() as u32) << 24;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000_0000 != 0 {
+                        (crc << 1) ^ 0x04C1_1DB7
+                    } else {
+                        crc << 1
+                    };
+                }
+            }
+            !crc.reverse_bits()
+        };
+        let image = parsed_asm.image_bytes().unwrap();
+        let expected = [
+            reference(&image[0..4]),
+            reference(&image[4..8]),
+            reference(&image[4..8]),
+            reference(&[]),
+        ];
+        for (fields, expected) in segments.iter().zip(expected) {
+            assert_eq!(fields[3], format!("{:08x}", expected));
+        }
+        assert_eq!(reference(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_require_entry() {
+        let options = AssembleOptions {
+            require_entry: true,
+            ..AssembleOptions::default()
+        };
+        let source = "main:\n\taddi $t0, 1\n\tb main\n";
+        assert!(assemble_with_options(source, &AssembleOptions::default()).is_ok());
+        let errs = assemble_with_options(source, &options).err();
+        assert!(matches!(
+            errs.as_deref(),
+            Some([AssemblerError::MissingEntry])
+        ));
+        let declared = format!(".global main\n{}", source);
+        let parsed_asm = assemble_with_options(&declared, &options);
+        assert!(parsed_asm.is_ok());
+        assert_eq!(parsed_asm.unwrap().entry.as_deref(), Some("main"));
+        let dangling = format!(".entry start\n{}", source);
+        let errs = assemble_with_options(&dangling, &options).err();
+        assert!(matches!(
+            errs.as_deref(),
+            Some([AssemblerError::UndefinedLabel(label)]) if label == "start"
+        ));
+    }
+
+    #[test]
+    fn test_multiple_files() {
+        use std::{env, fs, process};
+        let dir = env::temp_dir();
+        let boot = dir.join(format!("gatherer-boot-{}.asm", process::id()));
+        let main = dir.join(format!("gatherer-main-{}.asm", process::id()));
+        // No trailing newline, so the files must not run together
+        fs::write(&boot, "start:\n\taddi $t0, 1\n\tb main").unwrap();
+        fs::write(&main, "main:\n\taddi $t0, 1\n\tb start\n").unwrap();
+        let parsed_asm = assemble_files(&[&boot, &main], &AssembleOptions::default());
+        fs::remove_file(&boot).unwrap();
+        fs::remove_file(&main).unwrap();
+        assert!(parsed_asm.is_ok());
+        let parsed_asm = parsed_asm.unwrap();
+        assert_eq!(parsed_asm.labels["main"], 2);
+        let target = |idx: usize| parsed_asm.instrs[idx].abs_label().and_then(AbsLabel::addr);
+        assert_eq!(target(1), Some(8));
+        assert_eq!(target(3), Some(0));
+        assert_eq!(join_sources(&["a", "", "b\n"]), "a\nb\n");
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn test_gzip_input() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::{env, fs, process};
+        let path = env::temp_dir().join(format!("gatherer-gzip-{}.asm.gz", process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        write!(encoder, "Loop:\n\taddi $t2, 657\n\tb Loop\n").unwrap();
+        encoder.finish().unwrap();
+        let parsed_asm = assemble(&path, &AssembleOptions::default());
+        fs::remove_file(&path).unwrap();
+        assert!(parsed_asm.is_ok());
+        let image = parsed_asm.unwrap().image().unwrap();
+        let values: Vec<_> = image.iter().map(|word| word.value()).collect();
+        assert_eq!(values, vec![0x09400291, 0x38000000]);
+    }
+
+    #[cfg(not(feature = "flate2"))]
+    #[test]
+    fn test_gzip_unsupported() {
+        let options = AssembleOptions {
+            gzip: true,
+            ..AssembleOptions::default()
+        };
+        let errs = assemble("data/add.asm", &options).err();
+        assert!(matches!(
+            errs.as_deref(),
+            Some([AssemblerError::GzipUnsupported])
+        ));
+    }
+}
@@ -1,118 +1,971 @@
+use clap::{Parser, Subcommand};
 use colored::*;
-use error::Result as AssemblerResult;
-use instruction::Instruction;
-use regex::Regex;
+use gatherer::{
+    assemble, assemble_reader_located,
+    coe::{self, CoeRadix},
+    emit_tcp, image, instruction,
+    isa::{self, OpcodeTable},
+    join_sources, mif, read_source, AssembleOptions, AssemblerError, Diagnostic, Endianness,
+    OutputFormat,
+};
 use std::{
-    collections::HashMap,
-    env,
-    fs::File,
-    io::{BufRead, BufReader, Write},
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
     path::Path,
     process,
+    str::FromStr,
 };
 
-mod error;
-mod instruction;
-
-#[macro_use]
-extern crate lazy_static;
+/// Path standing for stdin as an input and stdout as the output.
+const STDIO_PATH: &str = "-";
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!(
-            "{}",
-            format!("Usage: {} <input-file> <output-file>", args[0]).red()
-        );
-        process::exit(1);
-    } else {
-        let mut parsed_asm = match parse_file(&args[1]) {
-            Ok(v) => v,
-            Err(e) => {
-                eprintln!("{}", e.to_string().red());
-                process::exit(1);
+    // Exits on its own for `--help`, `--version` and malformed flags
+    let mut cli = Cli::parse();
+    if let Some(command) = cli.command.take() {
+        run_command(command);
+        return;
+    }
+    if let Some(path) = &cli.dump_isa_table {
+        let written = fs::File::create(path)
+            .map_err(AssemblerError::from)
+            .and_then(|mut file| OpcodeTable::default().write(&mut file));
+        if let Err(err) = written {
+            fail(err);
+        }
+        return;
+    }
+    let mut cli = match CliArgs::from_cli(cli) {
+        Ok(cli) => cli,
+        Err(msg) => fail(msg),
+    };
+    if let Some(path) = &cli.isa {
+        let table = fs::read_to_string(path)
+            .map_err(AssemblerError::from)
+            .and_then(|text| OpcodeTable::parse(&text));
+        match table {
+            Ok(table) => cli.options.isa = Some(table),
+            Err(err) => fail(err),
+        }
+    }
+    if cli.batch {
+        match assemble_dir(&cli.inputs[0], &cli.output, &cli) {
+            Ok(summary) => {
+                println!("{} passed, {} failed", summary.passed, summary.failed);
+                if summary.failed > 0 {
+                    process::exit(1);
+                }
+            }
+            Err(err) => fail(AssemblerError::from(err)),
+        }
+    } else if let Err(errs) = assemble_file(&cli.inputs, &cli.output, &cli) {
+        report(&errs);
+    }
+}
+
+/// Runs one of the subcommands that don't assemble a program.
+fn run_command(command: Command) {
+    match command {
+        Command::Explain { mnemonic } => match isa::explain(&mnemonic) {
+            Ok(text) => print!("{}", text),
+            Err(err) => fail(err),
+        },
+        Command::Validate { file } => {
+            let text = match fs::read_to_string(&file) {
+                Ok(text) => text,
+                Err(err) => fail(AssemblerError::from(err)),
+            };
+            let summary = if Path::new(&file).extension().is_some_and(|ext| ext == "mif") {
+                mif::validate(&text).map(|summary| {
+                    format!(
+                        "valid MIF: {} of {} words of {} bits",
+                        summary.words, summary.depth, summary.width
+                    )
+                })
+            } else {
+                coe::validate(&text).map(|summary| {
+                    format!(
+                        "valid COE: {} words in radix {}",
+                        summary.words, summary.radix
+                    )
+                })
+            };
+            match summary {
+                Ok(summary) => println!("{}", summary.green()),
+                Err(err) => fail(err),
             }
+        }
+        Command::Equal { first, second } => {
+            let options = AssembleOptions::default();
+            let (first, second) = match (assemble(&first, &options), assemble(&second, &options)) {
+                (Ok(first), Ok(second)) => (first, second),
+                (Err(errs), _) | (_, Err(errs)) => report(&errs),
+            };
+            match first.first_difference(&second) {
+                Ok(None) => println!("{}", "programs are identical".green()),
+                Ok(Some(addr)) => fail(format!(
+                    "programs differ at address {}",
+                    first.options.format_addr(addr as usize)
+                )),
+                Err(err) => fail(err),
+            }
+        }
+        Command::Diffimg { first, second } => {
+            let (first, second) = match (fs::read(&first), fs::read(&second)) {
+                (Ok(first), Ok(second)) => (first, second),
+                (Err(err), _) | (_, Err(err)) => fail(AssemblerError::from(err)),
+            };
+            let diffs = image::diff(&first, &second);
+            if diffs.is_empty() {
+                println!("{}", "images are identical".green());
+                return;
+            }
+            let options = AssembleOptions::default();
+            let show = |word: Option<u32>| match word {
+                Some(word) => format!("0x{:08x}", word),
+                None => String::from("----------"),
+            };
+            for diff in &diffs {
+                println!(
+                    "{}: {} -> {}",
+                    options.format_addr(diff.addr),
+                    show(diff.old),
+                    show(diff.new)
+                );
+            }
+            fail(format!("images differ in {} word(s)", diffs.len()));
+        }
+    }
+}
+
+fn report<T: Display>(errs: &[T]) -> ! {
+    for err in errs {
+        eprintln!("{}", err.to_string().red());
+    }
+    process::exit(1);
+}
+
+/// Assembles the inputs as one program. Errors come back rendered
+/// against the source, ready to print.
+fn assemble_file<P, Q>(inputs: &[P], output: Q, cli: &CliArgs) -> Result<(), Vec<String>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let sources = read_inputs(inputs, &cli.options)?;
+    let options = AssembleOptions {
+        bin_header: cli.bin_header.then(|| header_name(inputs)),
+        ..cli.options.clone()
+    };
+    let parsed_asm = assemble_reader_located(join_sources(&sources).as_bytes(), &options)
+        .map_err(|diags| render_diagnostics(diags, inputs, &sources))?;
+    for warning in &parsed_asm.warnings {
+        let message = match warning.span.line {
+            0 => warning.message.clone(),
+            line => format!("{} at line {}", warning.message, line),
         };
-        parsed_asm.assign_labels(0);
-        if let Err(err) = parsed_asm.write_coe(&args[2]) {
-            eprintln!("{}", err.to_string().red());
-            process::exit(1);
-        }
-    }
-}
-
-struct ParsedAsm {
-    instrs: Vec<Instruction>,
-    labels: HashMap<String, usize>,
-}
-
-impl ParsedAsm {
-    fn assign_labels(&mut self, off: u32) {
-        for (idx, instr) in self.instrs.iter_mut().enumerate() {
-            if instr.has_abs_label() {
-                // Since our memory is small, we can
-                // directly store the PC value in an AbsLabel
-                let label_idx = self.labels[instr.get_label_name()] as u32;
-                instr.set_abs_addr(off + 4 * label_idx);
-            } else if instr.has_rel_label() {
-                // It is relative to PC + 4
-                let next_pc = 4 * (idx as u32 + 1);
-                let label_idx = self.labels[instr.get_label_name()] as u32;
-                let label_addr = 4 * label_idx;
-                // Diff can be negative, so signed type
-                let diff = label_addr as i32 - next_pc as i32;
-                let imm = (diff as i16 >> 2) as u16;
-                // But eventually, we want unsigned!
-                instr.set_rel_addr(imm);
+        eprintln!("{}", message.yellow());
+    }
+    if cli.list_unreachable {
+        let mut last_line = None;
+        for idx in parsed_asm.unreachable_instrs() {
+            let line = parsed_asm.spans[idx].line;
+            if last_line != Some(line) {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "unreachable instruction at line {} ({})",
+                        line,
+                        parsed_asm
+                            .options
+                            .format_addr(parsed_asm.options.base_addr as usize + 4 * idx)
+                    )
+                    .yellow()
+                );
+                last_line = Some(line);
             }
         }
     }
+    if cli.check_hazards {
+        for hazard in parsed_asm.data_hazards(cli.options.hazard_distance) {
+            eprintln!(
+                "{}",
+                format!(
+                    "read-after-write hazard on {}: line {} reads the value written at line {}",
+                    instruction::register_name(hazard.reg),
+                    parsed_asm.spans[hazard.consumer].line,
+                    parsed_asm.spans[hazard.producer].line
+                )
+                .yellow()
+            );
+        }
+    }
+    if cli.list_nop_runs {
+        for run in parsed_asm.nop_runs() {
+            eprintln!(
+                "{}",
+                format!(
+                    "{} consecutive nops at line {} ({}) could be reduced",
+                    run.len(),
+                    parsed_asm.spans[run.start].line,
+                    parsed_asm
+                        .options
+                        .format_addr(parsed_asm.options.base_addr as usize + 4 * run.start)
+                )
+                .yellow()
+            );
+        }
+    }
+    // Reports go to stderr when stdout carries the image itself
+    let to_stdout = output.as_ref() == Path::new(STDIO_PATH);
+    let report_writer = || -> Box<dyn Write> {
+        if to_stdout {
+            Box::new(io::stderr().lock())
+        } else {
+            Box::new(io::stdout().lock())
+        }
+    };
+    if cli.stats {
+        parsed_asm
+            .write_stats(&mut report_writer())
+            .map_err(|err| vec![err.to_string()])?;
+    }
+    if cli.dump_expanded {
+        parsed_asm
+            .write_expanded(&mut report_writer())
+            .map_err(|err| vec![err.to_string()])?;
+    }
+    if to_stdout {
+        parsed_asm.write_to(cli.format, &mut io::stdout().lock(), "program")
+    } else {
+        parsed_asm.write(cli.format, &output)
+    }
+    .map_err(|err| vec![err.to_string()])?;
+    for target in &cli.emit {
+        match target {
+            EmitTarget::Tcp(addr) => parsed_asm
+                .bin_bytes(cli.endian)
+                .and_then(|bytes| emit_tcp(addr, &bytes)),
+            EmitTarget::Dot => parsed_asm.write_dot(&mut report_writer()),
+        }
+        .map_err(|err| vec![err.to_string()])?;
+    }
+    if let Some(path) = &cli.map {
+        fs::File::create(path)
+            .map_err(AssemblerError::from)
+            .and_then(|mut file| parsed_asm.write_map(&mut file))
+            .map_err(|err| vec![err.to_string()])?;
+    }
+    if let Some(path) = &cli.bin {
+        fs::File::create(path)
+            .map_err(AssemblerError::from)
+            .and_then(|mut file| parsed_asm.write_bin(&mut file, cli.endian))
+            .map_err(|err| vec![err.to_string()])?;
+    }
+    if let Some(path) = &cli.listing {
+        fs::File::create(path)
+            .map_err(AssemblerError::from)
+            .and_then(|mut file| parsed_asm.write_listing(&mut file))
+            .map_err(|err| vec![err.to_string()])?;
+    }
+    if let Some(mode) = cli
+        .mode
+        .filter(|_| output.as_ref() != Path::new(STDIO_PATH))
+    {
+        set_mode(&output, mode).map_err(|err| vec![err.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Source name recorded in a binary header: the first input's file name.
+fn header_name<P: AsRef<Path>>(inputs: &[P]) -> String {
+    match inputs.first().map(AsRef::as_ref) {
+        Some(path) if path == Path::new(STDIO_PATH) => String::from("stdin"),
+        Some(path) => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        None => String::new(),
+    }
+}
 
-    fn write_coe<P: AsRef<Path>>(&self, path: P) -> AssemblerResult<()> {
-        let mut file = File::create(path)?;
-        writeln!(&mut file, "memory_initialization_radix=2;")?;
-        writeln!(&mut file, "memory_initialization_vector=")?;
-        for (idx, instr) in self.instrs.iter().enumerate() {
-            write!(&mut file, "{:032b}", instr.encode()?)?;
-            if idx == self.instrs.len() - 1 {
-                writeln!(&mut file, ";")?;
+/// Reads each input's source, taking `-` to mean stdin.
+fn read_inputs<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &AssembleOptions,
+) -> Result<Vec<String>, Vec<String>> {
+    inputs
+        .iter()
+        .map(|input| {
+            if input.as_ref() == Path::new(STDIO_PATH) {
+                let mut source = String::new();
+                io::stdin()
+                    .lock()
+                    .read_to_string(&mut source)
+                    .map(|_| source)
+                    .map_err(AssemblerError::from)
             } else {
-                writeln!(&mut file, ",")?;
+                read_source(input, options)
             }
-        }
-        Ok(())
+        })
+        .collect::<Result<_, _>>()
+        .map_err(|err| vec![err.to_string()])
+}
+
+/// Renders each diagnostic against the file its line falls in. With
+/// several inputs the lines are renumbered from the start of each file,
+/// which is named above the rendered line.
+fn render_diagnostics<P: AsRef<Path>>(
+    diags: Vec<Diagnostic>,
+    inputs: &[P],
+    sources: &[String],
+) -> Vec<String> {
+    if sources.len() == 1 {
+        return diags.iter().map(|diag| diag.render(&sources[0])).collect();
     }
+    let joined = join_sources(sources);
+    diags
+        .into_iter()
+        .map(|mut diag| {
+            let mut first_line = 1;
+            for (input, source) in inputs.iter().zip(sources) {
+                let lines = source.lines().count();
+                if (first_line..first_line + lines).contains(&diag.span.line) {
+                    diag.span.line -= first_line - 1;
+                    return format!("{}:\n{}", input.as_ref().display(), diag.render(source));
+                }
+                first_line += lines;
+            }
+            diag.render(&joined)
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct BatchSummary {
+    passed: usize,
+    failed: usize,
 }
 
-fn parse_file<P: AsRef<Path>>(filename: P) -> AssemblerResult<ParsedAsm> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let mut instrs = Vec::new();
-    let mut labels = HashMap::new();
-    for line in reader.lines() {
-        let line = line?;
-        let mut line = line.trim();
-        // Ignore line comments
-        if line.starts_with("//") {
-            continue;
+fn assemble_dir<P, Q>(src_dir: P, out_dir: Q, cli: &CliArgs) -> io::Result<BatchSummary>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut sources = Vec::new();
+    for entry in fs::read_dir(src_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "asm") {
+            sources.push(path);
         }
-        // Ignore end-of-line comments
-        if let Some(slash_idx) = line.find("//") {
-            line = &line[..slash_idx];
+    }
+    sources.sort();
+    fs::create_dir_all(&out_dir)?;
+    let mut summary = BatchSummary {
+        passed: 0,
+        failed: 0,
+    };
+    for source in sources {
+        let output = out_dir
+            .as_ref()
+            .join(source.file_name().unwrap())
+            .with_extension(cli.format.extension());
+        // Each program gets its own side outputs, named after its source
+        let side_output = |path: &Option<String>| {
+            path.as_deref()
+                .map(|path| source_side_output(path, &source))
+        };
+        let file_cli = CliArgs {
+            map: side_output(&cli.map),
+            bin: side_output(&cli.bin),
+            listing: side_output(&cli.listing),
+            ..cli.clone()
+        };
+        match assemble_file(&[&source], &output, &file_cli) {
+            Ok(()) => {
+                println!("{} {}", "ok".green(), source.display());
+                summary.passed += 1;
+            }
+            Err(errs) => {
+                eprintln!("{} {}", "FAILED".red(), source.display());
+                for err in &errs {
+                    eprintln!("    {}", err.replace('\n', "\n    ").red());
+                }
+                summary.failed += 1;
+            }
         }
-        match detect_label(line) {
-            Some(label) => {
-                labels.insert(label, instrs.len());
+    }
+    Ok(summary)
+}
+
+/// Replaces the file stem of a side-output `path` with that of `source`,
+/// keeping its directory and extension: `maps/out.map` becomes
+/// `maps/good.map` for `good.asm`.
+fn source_side_output(path: &str, source: &Path) -> String {
+    let path = Path::new(path);
+    let mut name = source.file_stem().unwrap_or_default().to_os_string();
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Assembles programs into COE and other memory images.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Source files assembled in order as one program, then the output
+    /// file; `-` reads stdin or writes stdout. With `--batch`, the source
+    /// and output directories
+    #[arg(value_name = "FILE")]
+    files: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Write the built-in opcode table to a file and exit
+    #[arg(long, value_name = "PATH")]
+    dump_isa_table: Option<String>,
+    /// Pad the output with zero words to exactly this size
+    #[arg(long, value_name = "BYTES")]
+    image_size: Option<usize>,
+    /// Pad the output with zero words to this memory depth
+    #[arg(long, value_name = "WORDS")]
+    depth: Option<usize>,
+    /// Bits per memory word. Below 32, immediates and jump addresses
+    /// shrink to fit and instructions need at least 16 bits
+    #[arg(
+        long,
+        value_name = "BITS",
+        default_value_t = instruction::DEFAULT_WORD_WIDTH,
+        value_parser = clap::value_parser!(u32).range(1..=64)
+    )]
+    word_width: u32,
+    /// Decompress the input (implied by a .gz extension)
+    #[arg(long)]
+    gzip: bool,
+    /// Reject operands not written as `mnemonic op1, op2`
+    #[arg(long)]
+    strict_format: bool,
+    /// Reject pseudo-instructions instead of expanding them
+    #[arg(long)]
+    no_pseudo: bool,
+    /// Radix of COE words: 2, 10 or 16
+    #[arg(long, value_name = "RADIX", default_value = "2", value_parser = parse_coe_radix)]
+    coe_radix: CoeRadix,
+    /// Print decimal COE words as signed values
+    #[arg(long)]
+    signed: bool,
+    /// Set the output file's permissions (Unix only)
+    #[arg(long, value_name = "OCTAL", value_parser = parse_mode)]
+    mode: Option<u32>,
+    /// Assemble every .asm in a directory, continuing past errors. Map,
+    /// binary and listing paths take each source's file stem
+    #[arg(long)]
+    batch: bool,
+    /// Read the source from stdin, like an input file of `-`
+    #[arg(long)]
+    stdin: bool,
+    /// Output format: coe, mif, readmemh (or hex, verilog), readmemb (or
+    /// binb), c-header (or c), python, python-list or bin
+    #[arg(long, default_value = "coe")]
+    format: OutputFormat,
+    /// Warn about instructions no control flow reaches
+    #[arg(long)]
+    list_unreachable: bool,
+    /// Suggest runs of consecutive nops that could be reduced
+    #[arg(long)]
+    list_nop_runs: bool,
+    /// Print the instruction stream after pseudo-instruction expansion
+    #[arg(long)]
+    dump_expanded: bool,
+    /// Warn about read-after-write hazards without interlocks
+    #[arg(long)]
+    check_hazards: bool,
+    /// Insert nops to resolve read-after-write hazards
+    #[arg(long)]
+    fix_hazards: bool,
+    /// Pipeline distance used by hazard checks
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    hazard_distance: usize,
+    /// Encode with opcodes from a table written by --dump-isa-table
+    #[arg(long, value_name = "FILE")]
+    isa: Option<String>,
+    /// Store the image highest address first
+    #[arg(long)]
+    reverse: bool,
+    /// Make warnings about confusing label names errors
+    #[arg(long)]
+    strict: bool,
+    /// Fail unless the program declares `.entry` or `.global`
+    #[arg(long)]
+    require_entry: bool,
+    /// Stop at the first error instead of reporting every one
+    #[arg(long)]
+    fail_fast: bool,
+    /// Print mnemonic and register usage statistics
+    #[arg(long)]
+    stats: bool,
+    /// Write every label with its address and segment CRC to a map file
+    #[arg(long, value_name = "PATH")]
+    map: Option<String>,
+    /// Write each source line with its address and encoding
+    #[arg(long, value_name = "PATH")]
+    listing: Option<String>,
+    /// Also write the image as raw bytes
+    #[arg(long, value_name = "PATH")]
+    bin: Option<String>,
+    /// Start --bin, --format bin and tcp images with a header naming the
+    /// source
+    #[arg(long)]
+    bin_header: bool,
+    /// Byte order of --bin, --format bin and tcp image words: big or little
+    #[arg(long, value_name = "ORDER", default_value = "big")]
+    endian: Endianness,
+    /// Also stream the raw image to tcp:<host>:<port>, or print the
+    /// control-flow graph as Graphviz with dot
+    #[arg(long, value_name = "TARGET")]
+    emit: Vec<EmitTarget>,
+    /// Hex digits used when printing addresses [default: 8]
+    #[arg(long, value_name = "N")]
+    addr_digits: Option<usize>,
+}
+
+fn parse_coe_radix(value: &str) -> Result<CoeRadix, String> {
+    match value {
+        "2" => Ok(CoeRadix::Binary),
+        "10" => Ok(CoeRadix::Decimal { signed: false }),
+        "16" => Ok(CoeRadix::Hex),
+        _ => Err(format!(
+            "unsupported COE radix `{}`, expected 2, 10 or 16",
+            value
+        )),
+    }
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8).map_err(|_| format!("invalid octal mode `{}`", value))
+}
+
+#[cfg(unix)]
+fn set_mode<P: AsRef<Path>>(path: P, mode: u32) -> io::Result<()> {
+    use std::{fs, os::unix::fs::PermissionsExt};
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode<P: AsRef<Path>>(_path: P, _mode: u32) -> io::Result<()> {
+    eprintln!(
+        "{}",
+        "`--mode` is only supported on Unix, leaving permissions unchanged".yellow()
+    );
+    Ok(())
+}
+
+fn fail<T: Display>(msg: T) -> ! {
+    eprintln!("{}", msg.to_string().red());
+    process::exit(1);
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+enum Command {
+    /// Describe the encoding of an instruction
+    Explain { mnemonic: String },
+    /// Check that two programs assemble to the same image
+    Equal { first: String, second: String },
+    /// Compare two raw images word by word
+    Diffimg { first: String, second: String },
+    /// Check the structure of a COE or MIF file
+    Validate {
+        #[arg(value_name = "COE_OR_MIF")]
+        file: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CliArgs {
+    // Source files assembled in order as one program, or the source
+    // directory with `--batch`
+    inputs: Vec<String>,
+    output: String,
+    mode: Option<u32>,
+    batch: bool,
+    list_unreachable: bool,
+    list_nop_runs: bool,
+    dump_expanded: bool,
+    check_hazards: bool,
+    stats: bool,
+    emit: Vec<EmitTarget>,
+    // Path of an opcode table to encode with
+    isa: Option<String>,
+    // Path to write the label map to
+    map: Option<String>,
+    // Path to write the address/encoding/source listing to
+    listing: Option<String>,
+    // Path to write the raw image to, and its byte order
+    bin: Option<String>,
+    endian: Endianness,
+    // Whether raw images start with a provenance header
+    bin_header: bool,
+    format: OutputFormat,
+    options: AssembleOptions,
+}
+
+impl CliArgs {
+    /// Parses the arguments after the program name.
+    #[cfg(test)]
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let args = std::iter::once("gatherer").chain(args.iter().map(String::as_str));
+        let cli = Cli::try_parse_from(args).map_err(|err| err.to_string())?;
+        Self::from_cli(cli)
+    }
+
+    /// Checks the combinations clap can't and gathers the library options.
+    fn from_cli(cli: Cli) -> Result<Self, String> {
+        let coe_radix = match cli.coe_radix {
+            CoeRadix::Decimal { .. } => CoeRadix::Decimal { signed: cli.signed },
+            CoeRadix::Binary | CoeRadix::Hex if cli.signed => {
+                return Err(String::from("`--signed` requires `--coe-radix 10`"))
             }
-            None => instrs.extend(Instruction::from_str(line)?),
+            radix => radix,
+        };
+        let mut positional = cli.files;
+        if cli.stdin {
+            positional.insert(0, String::from(STDIO_PATH));
+        }
+        if cli.batch && positional.iter().any(|path| path == STDIO_PATH) {
+            return Err(String::from(
+                "`--batch` needs directories, not stdin or stdout",
+            ));
+        }
+        if positional.len() < 2 || (cli.batch && positional.len() != 2) {
+            return Err(format!(
+                "expected input and output files, found {} arguments",
+                positional.len()
+            ));
         }
+        let output = positional.pop().unwrap();
+        if cli.gzip && positional.iter().any(|path| path == STDIO_PATH) {
+            return Err(String::from(
+                "`--gzip` can't decompress stdin; pipe it through `gunzip` instead",
+            ));
+        }
+        Ok(Self {
+            inputs: positional,
+            output,
+            mode: cli.mode,
+            batch: cli.batch,
+            list_unreachable: cli.list_unreachable,
+            list_nop_runs: cli.list_nop_runs,
+            dump_expanded: cli.dump_expanded,
+            check_hazards: cli.check_hazards,
+            stats: cli.stats,
+            emit: cli.emit,
+            isa: cli.isa,
+            map: cli.map,
+            listing: cli.listing,
+            bin: cli.bin,
+            endian: cli.endian,
+            bin_header: cli.bin_header,
+            format: match cli.format {
+                OutputFormat::Bin(_) => OutputFormat::Bin(cli.endian),
+                format => format,
+            },
+            options: AssembleOptions {
+                image_size: cli.image_size,
+                depth: cli.depth,
+                word_width: cli.word_width,
+                coe_radix,
+                gzip: cli.gzip,
+                strict_format: cli.strict_format,
+                no_pseudo: cli.no_pseudo,
+                addr_digits: cli.addr_digits,
+                hazard_distance: cli.hazard_distance,
+                fix_hazards: cli.fix_hazards,
+                strict: cli.strict,
+                require_entry: cli.require_entry,
+                reverse: cli.reverse,
+                fail_fast: cli.fail_fast,
+                ..AssembleOptions::default()
+            },
+        })
+    }
+}
+
+/// Extra artifact produced alongside the output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EmitTarget {
+    // `host:port` to stream the raw image to
+    Tcp(String),
+    // Control-flow graph in Graphviz DOT, on stdout
+    Dot,
+}
+
+impl FromStr for EmitTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        if target == "dot" {
+            return Ok(EmitTarget::Dot);
+        }
+        target
+            .strip_prefix("tcp:")
+            .filter(|addr| addr.contains(':'))
+            .map(|addr| EmitTarget::Tcp(String::from(addr)))
+            .ok_or_else(|| {
+                format!(
+                    "unsupported emit target `{}`, expected tcp:host:port or dot",
+                    target
+                )
+            })
     }
-    Ok(ParsedAsm { instrs, labels })
 }
 
-fn detect_label(line: &str) -> Option<String> {
-    lazy_static! {
-        static ref LABEL_RE: Regex = Regex::new(r"(\.?[a-zA-Z0-9_]+):").unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_cli_image_size() {
+        let args: Vec<_> = ["--image-size", "32", "in.asm", "out.coe", "--mode", "0640"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        let cli = CliArgs::parse(&args);
+        assert_eq!(
+            cli,
+            Ok(CliArgs {
+                inputs: vec![String::from("in.asm")],
+                output: String::from("out.coe"),
+                mode: Some(0o640),
+                batch: false,
+                list_unreachable: false,
+                list_nop_runs: false,
+                dump_expanded: false,
+                check_hazards: false,
+                stats: false,
+                emit: Vec::new(),
+                isa: None,
+                map: None,
+                listing: None,
+                bin: None,
+                endian: Endianness::Big,
+                bin_header: false,
+                format: OutputFormat::Coe,
+                options: AssembleOptions {
+                    image_size: Some(32),
+                    ..AssembleOptions::default()
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_cli_flags() {
+        let args: Vec<_> = [
+            "--format",
+            "mif",
+            "--coe-radix",
+            "10",
+            "--signed",
+            "--depth",
+            "64",
+            "--word-width",
+            "64",
+            "--emit",
+            "dot",
+            "--emit",
+            "tcp:localhost:9000",
+            "--bin",
+            "out.bin",
+            "--endian",
+            "little",
+            "--fail-fast",
+            "--list-nop-runs",
+            "--hazard-distance",
+            "2",
+            "--stdin",
+            "-",
+        ]
+        .iter()
+        .map(|arg| String::from(*arg))
+        .collect();
+        let cli = CliArgs::parse(&args);
+        assert_eq!(
+            cli,
+            Ok(CliArgs {
+                inputs: vec![String::from("-")],
+                output: String::from("-"),
+                mode: None,
+                batch: false,
+                list_unreachable: false,
+                list_nop_runs: true,
+                dump_expanded: false,
+                check_hazards: false,
+                stats: false,
+                emit: vec![
+                    EmitTarget::Dot,
+                    EmitTarget::Tcp(String::from("localhost:9000"))
+                ],
+                isa: None,
+                map: None,
+                listing: None,
+                bin: Some(String::from("out.bin")),
+                endian: Endianness::Little,
+                bin_header: false,
+                format: OutputFormat::Mif,
+                options: AssembleOptions {
+                    coe_radix: CoeRadix::Decimal { signed: true },
+                    depth: Some(64),
+                    word_width: 64,
+                    fail_fast: true,
+                    hazard_distance: 2,
+                    ..AssembleOptions::default()
+                },
+            })
+        );
+        let args = [String::from("--depth"), String::from("lots")];
+        assert!(CliArgs::parse(&args).is_err());
+        let args = ["--word-width", "0", "in.asm", "out.coe"].map(String::from);
+        assert!(CliArgs::parse(&args).is_err());
+        let args = ["--gzip", "--stdin", "out.coe"].map(String::from);
+        assert!(matches!(CliArgs::parse(&args), Err(msg) if msg.contains("--gzip")));
+        let args = ["--gzip", "in.asm.gz", "-"].map(String::from);
+        assert!(CliArgs::parse(&args).is_ok());
+    }
+
+    #[test]
+    fn test_cli_multiple_inputs() {
+        let args: Vec<_> = ["boot.asm", "main.asm", "out.coe"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        let cli = CliArgs::parse(&args);
+        assert!(cli.is_ok());
+        let cli = cli.unwrap();
+        assert_eq!(cli.inputs, vec!["boot.asm", "main.asm"]);
+        assert_eq!(cli.output, "out.coe");
+        let args: Vec<_> = ["--batch", "src", "more", "out"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        assert!(CliArgs::parse(&args).is_err());
+    }
+
+    #[test]
+    fn test_cli_commands() {
+        let cli = Cli::try_parse_from(["gatherer", "explain", "addi"]);
+        assert!(cli.is_ok());
+        assert_eq!(
+            cli.unwrap().command,
+            Some(Command::Explain {
+                mnemonic: String::from("addi")
+            })
+        );
+        let cli = Cli::try_parse_from(["gatherer", "diffimg", "old.bin", "new.bin"]);
+        assert!(cli.is_ok());
+        assert_eq!(
+            cli.unwrap().command,
+            Some(Command::Diffimg {
+                first: String::from("old.bin"),
+                second: String::from("new.bin")
+            })
+        );
+        let help = Cli::try_parse_from(["gatherer", "explain", "--help"]);
+        assert!(matches!(
+            help,
+            Err(err) if err.kind() == clap::error::ErrorKind::DisplayHelp
+        ));
+        assert!(Cli::try_parse_from(["gatherer", "validate"]).is_err());
+        let cli = Cli::try_parse_from(["gatherer", "--dump-isa-table", "isa.txt"]);
+        assert!(cli.is_ok());
+        assert_eq!(cli.unwrap().dump_isa_table, Some(String::from("isa.txt")));
+        // Other files are still sources and outputs
+        let cli = Cli::try_parse_from(["gatherer", "main.asm", "out.coe"]);
+        assert!(cli.is_ok());
+        assert_eq!(cli.unwrap().command, None);
+    }
+
+    #[test]
+    fn test_cli_coe_radix() {
+        let args: Vec<_> = ["--signed", "in.asm", "out.coe"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        assert!(CliArgs::parse(&args).is_err());
+        let args: Vec<_> = ["--coe-radix", "8", "in.asm", "out.coe"]
+            .iter()
+            .map(|arg| String::from(*arg))
+            .collect();
+        assert!(CliArgs::parse(&args).is_err());
+    }
+
+    #[test]
+    fn test_batch_dir() {
+        let root = env::temp_dir().join(format!("gatherer-batch-{}", process::id()));
+        let src_dir = root.join("src");
+        let out_dir = root.join("out");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("good.asm"), "Loop:\n\taddi $t0, 1\n\tb Loop\n").unwrap();
+        fs::write(src_dir.join("bad.asm"), "\tb Nowhere\n").unwrap();
+        fs::write(src_dir.join("notes.txt"), "not assembly\n").unwrap();
+        fs::write(src_dir.join("other.asm"), "start:\n\taddi $t0, 2\n").unwrap();
+        let side = |ext: &str| out_dir.join(format!("side.{}", ext));
+        let side = [side("map"), side("bin"), side("lst")].map(|path| path.display().to_string());
+        let args: Vec<_> = [
+            "--batch",
+            "--map",
+            &side[0],
+            "--bin",
+            &side[1],
+            "--listing",
+            &side[2],
+            "src",
+            "out",
+        ]
+        .iter()
+        .map(|arg| String::from(*arg))
+        .collect();
+        let cli = CliArgs::parse(&args).unwrap();
+        let summary = assemble_dir(&src_dir, &out_dir, &cli);
+        let good_written = out_dir.join("good.coe").exists();
+        let bad_written = out_dir.join("bad.coe").exists();
+        let read = |name: &str| fs::read(out_dir.join(name)).unwrap_or_default();
+        let sides = ["map", "bin", "lst"].map(|ext| {
+            (
+                read(&format!("good.{}", ext)),
+                read(&format!("other.{}", ext)),
+            )
+        });
+        let shared_written = side.iter().any(|path| Path::new(path).exists());
+        fs::remove_dir_all(&root).unwrap();
+        assert!(!shared_written);
+        for (good, other) in &sides {
+            assert!(!good.is_empty() && !other.is_empty());
+            assert_ne!(good, other);
+        }
+        assert!(summary.is_ok());
+        assert_eq!(
+            summary.unwrap(),
+            BatchSummary {
+                passed: 2,
+                failed: 1
+            }
+        );
+        assert!(good_written);
+        assert!(!bad_written);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("gatherer-mode-{}.coe", process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(set_mode(&path, 0o640).is_ok());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o640);
     }
-    LABEL_RE.captures(line).map(|caps| String::from(&caps[1]))
 }
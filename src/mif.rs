@@ -0,0 +1,184 @@
+use crate::error::{AssemblerError, Result as AssemblerResult};
+
+/// Summary of a structurally valid MIF file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MifSummary {
+    pub depth: usize,
+    pub width: u32,
+    // Addresses given a value, counting each address of a range
+    pub words: usize,
+}
+
+fn radix_of(name: &str) -> Option<u32> {
+    match name {
+        "BIN" => Some(2),
+        "OCT" => Some(8),
+        "DEC" | "UNS" => Some(10),
+        "HEX" => Some(16),
+        _ => None,
+    }
+}
+
+/// Checks the header, the `CONTENT BEGIN ... END;` block, and that every
+/// address lies within `DEPTH` and every value fits in `WIDTH` bits.
+pub fn validate(text: &str) -> AssemblerResult<MifSummary> {
+    let invalid = |msg: String| AssemblerError::InvalidMif(msg);
+    // `--` comments run to the end of the line
+    let body: Vec<_> = text
+        .lines()
+        .map(|line| line.split_once("--").map_or(line, |(code, _)| code))
+        .collect();
+    let body = body.join("\n");
+
+    let (header, content) = body
+        .split_once("CONTENT")
+        .ok_or_else(|| invalid(String::from("missing `CONTENT BEGIN`")))?;
+    let content = content
+        .trim_start()
+        .strip_prefix("BEGIN")
+        .ok_or_else(|| invalid(String::from("missing `BEGIN` after `CONTENT`")))?;
+    let content = content
+        .trim_end()
+        .strip_suffix("END;")
+        .ok_or_else(|| invalid(String::from("content is not terminated by `END;`")))?;
+
+    let mut depth = None;
+    let mut width = None;
+    let mut address_radix = 16;
+    let mut data_radix = 16;
+    let statements = header.trim_end();
+    let statements = statements.strip_suffix(';').unwrap_or(statements);
+    for statement in statements.split(';').map(str::trim) {
+        let (key, value) = statement
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("malformed header line `{}`", statement)))?;
+        let value = value.trim();
+        let number = || {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|&number| number > 0)
+                .ok_or_else(|| invalid(format!("invalid {} `{}`", key.trim(), value)))
+        };
+        let radix =
+            || radix_of(value).ok_or_else(|| invalid(format!("unsupported radix `{}`", value)));
+        match key.trim() {
+            "DEPTH" => depth = Some(number()?),
+            "WIDTH" => width = Some(number()? as u32),
+            "ADDRESS_RADIX" => address_radix = radix()?,
+            "DATA_RADIX" => data_radix = radix()?,
+            other => return Err(invalid(format!("unknown header key `{}`", other))),
+        }
+    }
+    let depth = depth.ok_or_else(|| invalid(String::from("missing `DEPTH`")))?;
+    let width = width.ok_or_else(|| invalid(String::from("missing `WIDTH`")))?;
+
+    let address = |text: &str| {
+        usize::from_str_radix(text.trim(), address_radix)
+            .ok()
+            .filter(|&addr| addr < depth)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "address `{}` is outside depth {}",
+                    text.trim(),
+                    depth
+                ))
+            })
+    };
+    let mut words = 0;
+    let entries = content.trim_end();
+    let entries = match entries.strip_suffix(';') {
+        Some(entries) => entries,
+        None if entries.trim().is_empty() => entries,
+        None => return Err(invalid(String::from("entry is not terminated by `;`"))),
+    };
+    for entry in entries
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (addrs, values) = entry
+            .split_once(':')
+            .ok_or_else(|| invalid(format!("entry `{}` has no `:`", entry)))?;
+        let (first, last) = match addrs.trim().strip_prefix('[') {
+            Some(range) => {
+                let (first, last) = range
+                    .strip_suffix(']')
+                    .and_then(|range| range.split_once(".."))
+                    .ok_or_else(|| invalid(format!("malformed range `{}`", addrs.trim())))?;
+                (address(first)?, address(last)?)
+            }
+            None => {
+                let addr = address(addrs)?;
+                (addr, addr)
+            }
+        };
+        if last < first {
+            return Err(invalid(format!("range `{}` runs backward", addrs.trim())));
+        }
+        let values: Vec<_> = values.split_whitespace().collect();
+        if values.is_empty() {
+            return Err(invalid(format!("entry `{}` has no value", entry)));
+        }
+        for value in &values {
+            let digits = if data_radix == 10 {
+                value.strip_prefix('-').unwrap_or(value)
+            } else {
+                value
+            };
+            let fits = u64::from_str_radix(digits, data_radix)
+                .is_ok_and(|number| width >= 64 || number >> width == 0);
+            if !fits {
+                return Err(invalid(format!(
+                    "value `{}` is not a {}-bit word in radix {}",
+                    value, width, data_radix
+                )));
+            }
+        }
+        // Several values fill consecutive addresses from the first
+        words += if values.len() > 1 {
+            if first + values.len() > depth {
+                return Err(invalid(format!(
+                    "entry `{}` runs past depth {}",
+                    entry, depth
+                )));
+            }
+            values.len()
+        } else {
+            last - first + 1
+        };
+    }
+    Ok(MifSummary {
+        depth,
+        width,
+        words,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_mif() {
+        let valid = "DEPTH = 4;\nWIDTH = 8;\nADDRESS_RADIX = HEX;\nDATA_RADIX = HEX;\n\
+                     CONTENT BEGIN\n    0 : 1f; -- first\n    [1..3] : 00;\nEND;\n";
+        assert_eq!(
+            validate(valid).unwrap(),
+            MifSummary {
+                depth: 4,
+                width: 8,
+                words: 4
+            }
+        );
+        let unterminated = "DEPTH = 4;\nWIDTH = 8;\nCONTENT BEGIN\n    0 : 1f;\n";
+        assert!(matches!(
+            validate(unterminated),
+            Err(AssemblerError::InvalidMif(msg)) if msg.contains("END;")
+        ));
+        let too_deep = "DEPTH = 4;\nWIDTH = 8;\nCONTENT BEGIN\n    [0..4] : 00;\nEND;\n";
+        assert!(validate(too_deep).is_err());
+        let too_wide = "DEPTH = 4;\nWIDTH = 8;\nCONTENT BEGIN\n    0 : 100;\nEND;\n";
+        assert!(validate(too_wide).is_err());
+    }
+}
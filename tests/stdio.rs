@@ -0,0 +1,48 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn pipe(args: &[&str], source: &str) -> (bool, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gatherer"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let source = "Loop:\n\taddi $t0, 1\n\tb Loop\n";
+    let coe = "memory_initialization_radix=2;\nmemory_initialization_vector=\n\
+               00001001000000000000000000000001,\n\
+               00111000000000000000000000000000;\n";
+    assert_eq!(pipe(&["-", "-"], source), (true, String::from(coe)));
+    assert_eq!(pipe(&["--stdin", "-"], source), (true, String::from(coe)));
+    let (ok, stdout) = pipe(&["-", "-"], "\taddx $t0, 1\n");
+    assert!(!ok);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn test_reports_leave_stdout_to_the_image() {
+    let source = "Loop:\n\taddi $t0, 1\n\tb Loop\n";
+    let (ok, image) = pipe(&["-", "-"], source);
+    assert!(ok);
+    assert_eq!(pipe(&["--stats", "-", "-"], source), (true, image.clone()));
+    let args = ["--dump-expanded", "--emit", "dot", "-", "-"];
+    assert_eq!(pipe(&args, source), (true, image));
+}